@@ -0,0 +1,570 @@
+//! Contains the settings of rass. Settings are read from the user config file
+//! (`$XDG_CONFIG_HOME/rass/config.toml`, defaults to `~/.config/rass`), which
+//! can be overridden per store by a `.rass.toml` in the root of the store.
+//! Environment variables take precedence over both files. Since the store
+//! config comes with the store's remote, settings which name trusted keys or
+//! commands (`signing_keys`, `git.commit_signers`, `editor`, `clipboard`,
+//! `searcher`), other stores (`mounts`), push the store (`auto_push`) or
+//! control how changes are committed and checked (`vcs`, `git.backend`,
+//! `git.allowed_files`, `lock_timeout`) are only read from the user config.
+//!
+//! Example of a config file, all settings are optional:
+//!
+//! ```toml
+//! store = "~/.password-store"
+//! clip_time = 45
+//! clipboard = "system"
+//! editor = "vim"
+//! searcher = "grep"
+//! auto_push = false
+//! vcs = "auto"
+//! color = true
+//! lock_timeout = 10
+//!
+//! [generator]
+//! length = 25
+//! symbols = true
+//!
+//! [git]
+//! backend = "libgit2"
+//! message = "{message}"
+//! commit_signers = []
+//! allowed_files = ["README.md"]
+//!
+//! [index]
+//! fields = ["user", "url", "tags"]
+//!
+//! [mounts]
+//! team = "~/stores/team"
+//! ```
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::result;
+
+use toml;
+
+pub static STORE_DIR_ENV_NAME: &'static str = "PASSWORD_STORE_DIR";
+pub static CLIP_TIME_ENV_NAME: &'static str = "PASSWORD_STORE_CLIP_TIME";
+pub static SIGNING_KEY_ENV_NAME: &'static str = "PASSWORD_STORE_SIGNING_KEY";
+pub static GENERATED_LENGTH_ENV_NAME: &'static str =
+    "PASSWORD_STORE_GENERATED_LENGTH";
+pub static EDITOR_ENV_NAME: &'static str = "EDITOR";
+
+pub static CONFIG_FILE: &'static str = "config.toml";
+pub static STORE_CONFIG_FILE: &'static str = ".rass.toml";
+
+/// The clipboard backend which uses the system clipboard directly. Every
+/// other value of `clipboard` is treated as command, which gets the content
+/// to copy on stdin, e.g. `wl-copy` or `xclip -selection clipboard`. The
+/// password is cleared after `clip_time` unless the clipboard was changed in
+/// the meantime, which is only detected for `wl-copy`, `xclip`, `xsel` and
+/// `pbcopy`.
+pub static CLIPBOARD_SYSTEM: &'static str = "system";
+
+/// Detects the version control of the store.
+pub static VCS_AUTO: &'static str = "auto";
+/// Disables the version control, changes are not committed.
+pub static VCS_NONE: &'static str = "none";
+
+/// The git backend which uses libgit2 in-process.
+pub static GIT_BACKEND_LIBGIT2: &'static str = "libgit2";
+/// The git backend which runs the `git` command.
+pub static GIT_BACKEND_COMMAND: &'static str = "command";
+
+/// Commits with the message of the operation alone.
+pub static DEFAULT_COMMIT_MESSAGE: &'static str = "{message}";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+}
+
+pub type Result<T> = result::Result<T, ConfigError>;
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "IO error: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "Config error: {}", err),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::Io(_) => "io error",
+            ConfigError::Parse(ref err) => err,
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ConfigError::Io(ref err) => Some(err),
+            ConfigError::Parse(_) => None,
+        }
+    }
+}
+
+/// Holds all settings of rass, see the module documentation for the file
+/// format.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Location of the password store.
+    pub store: PathBuf,
+    /// Seconds until a copied password is removed from the clipboard.
+    pub clip_time: u64,
+    /// Clipboard backend, either `system` or a command.
+    pub clipboard: String,
+    /// Default length of generated passwords.
+    pub generate_length: usize,
+    /// Whether generated passwords contain symbols by default.
+    pub generate_symbols: bool,
+    /// Editor used for `edit`.
+    pub editor: String,
+    /// Default searcher used for `grep`.
+    pub searcher: String,
+    /// Push to the remote after every commit.
+    pub auto_push: bool,
+    /// Colorize the output.
+    pub color: bool,
+    /// Seconds to wait for the lock of a store held by another process.
+    pub lock_timeout: u64,
+    /// Fingerprints of the keys which have to sign `.gpg-id` files.
+    pub signing_keys: Vec<String>,
+    /// Stores which are mounted into the store, as prefix and location.
+    pub mounts: Vec<(String, PathBuf)>,
+    /// Fields of the search index, the index is disabled if empty.
+    pub index_fields: Vec<String>,
+    /// Version control of the store, either `auto` or `none`.
+    pub vcs: String,
+    /// Backend of git stores, either `libgit2` or `command`.
+    pub git_backend: String,
+    /// Template of commit messages, see `PassStore::set_message_template`.
+    pub commit_message: String,
+    /// Fingerprints of the keys which have to sign pulled commits, which
+    /// change entries or `.gpg-id` files. Not verified if empty.
+    pub commit_signers: Vec<String>,
+    /// Patterns of files which may be committed besides the gpg-files and
+    /// the files of rass, e.g. `README.md`.
+    pub allowed_files: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let mut store = env::home_dir().unwrap_or_default();
+        store.push(".password-store");
+
+        Config {
+            store: store,
+            clip_time: 45,
+            clipboard: String::from(CLIPBOARD_SYSTEM),
+            generate_length: 25,
+            generate_symbols: true,
+            editor: String::from("vim"),
+            searcher: String::from("grep"),
+            auto_push: false,
+            color: true,
+            lock_timeout: 10,
+            signing_keys: vec![],
+            mounts: vec![],
+            index_fields: vec![],
+            vcs: String::from(VCS_AUTO),
+            git_backend: String::from(GIT_BACKEND_LIBGIT2),
+            commit_message: String::from(DEFAULT_COMMIT_MESSAGE),
+            commit_signers: vec![],
+            allowed_files: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Loads the settings in the following order, where later ones override
+    /// earlier ones: defaults, the user config file, the store config file
+    /// and finally the environment variables.
+    pub fn load() -> Result<Config> {
+        let mut config = Config::default();
+
+        if let Some(path) = Config::user_config_file() {
+            if path.is_file() {
+                config.merge_file(&path)?;
+            }
+        }
+
+        // the store location has to be known before the store config is read
+        if let Ok(val) = env::var(STORE_DIR_ENV_NAME) {
+            config.store = PathBuf::from(val);
+        }
+
+        // the store config is shared through the remote of the store, hence
+        // it must not change the trusted keys or commands which are executed.
+        let store_config = config.store.join(STORE_CONFIG_FILE);
+        if store_config.is_file() {
+            let trusted = config.clone();
+            config.merge_file(&store_config)?;
+            config.keep_trusted(trusted);
+        }
+
+        config.merge_env()?;
+
+        Ok(config)
+    }
+
+    /// Restores the settings which are only read from the user config, as
+    /// given by `trusted`.
+    fn keep_trusted(&mut self, trusted: Config) {
+        self.store = trusted.store;
+        self.signing_keys = trusted.signing_keys;
+        self.commit_signers = trusted.commit_signers;
+        self.editor = trusted.editor;
+        self.clipboard = trusted.clipboard;
+        self.searcher = trusted.searcher;
+        self.mounts = trusted.mounts;
+        self.auto_push = trusted.auto_push;
+        self.vcs = trusted.vcs;
+        self.git_backend = trusted.git_backend;
+        self.allowed_files = trusted.allowed_files;
+        self.lock_timeout = trusted.lock_timeout;
+    }
+
+    /// Returns the location of the user config file.
+    pub fn user_config_file() -> Option<PathBuf> {
+        let base = match env::var("XDG_CONFIG_HOME") {
+            Ok(ref val) if !val.is_empty() => PathBuf::from(val),
+            _ => match env::home_dir() {
+                Some(home) => home.join(".config"),
+                None => return None,
+            },
+        };
+        Some(base.join("rass").join(CONFIG_FILE))
+    }
+
+    /// Reads the given config file and overrides all settings found in it.
+    pub fn merge_file(&mut self, path: &Path) -> Result<()> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+
+        self.merge_str(&content)
+            .map_err(|e| ConfigError::Parse(format!("{:?}: {}", path, e)))
+    }
+
+    /// Overrides all settings found in the given toml string.
+    pub fn merge_str(&mut self, content: &str) -> result::Result<(), String> {
+        let value = content.parse::<toml::Value>().map_err(|e| e.to_string())?;
+
+        if let Some(v) = value.get("store") {
+            self.store = expand_home(get_str(v, "store")?);
+        }
+        if let Some(v) = value.get("clip_time") {
+            self.clip_time = get_int(v, "clip_time")? as u64;
+        }
+        if let Some(v) = value.get("clipboard") {
+            self.clipboard = get_str(v, "clipboard")?.to_string();
+        }
+        if let Some(v) = value.get("editor") {
+            self.editor = get_str(v, "editor")?.to_string();
+        }
+        if let Some(v) = value.get("searcher") {
+            self.searcher = get_str(v, "searcher")?.to_string();
+        }
+        if let Some(v) = value.get("auto_push") {
+            self.auto_push = get_bool(v, "auto_push")?;
+        }
+        if let Some(v) = value.get("vcs") {
+            let vcs = get_str(v, "vcs")?;
+            if vcs != VCS_AUTO && vcs != VCS_NONE {
+                return Err(format!("vcs shall be \"{}\" or \"{}\"",
+                                   VCS_AUTO, VCS_NONE));
+            }
+            self.vcs = vcs.to_string();
+        }
+        if let Some(v) = value.get("color") {
+            self.color = get_bool(v, "color")?;
+        }
+        if let Some(v) = value.get("lock_timeout") {
+            self.lock_timeout = get_int(v, "lock_timeout")? as u64;
+        }
+        if let Some(v) = value.get("signing_keys") {
+            let keys = v.as_array()
+                .ok_or_else(|| String::from("signing_keys shall be an array"))?;
+            self.signing_keys = vec![];
+            for k in keys {
+                self.signing_keys.push(get_str(k, "signing_keys")?.to_string());
+            }
+        }
+        if let Some(mounts) = value.get("mounts") {
+            let mounts = mounts.as_table()
+                .ok_or_else(|| String::from("mounts shall be a table"))?;
+            self.mounts = vec![];
+            for (prefix, path) in mounts {
+                let path = expand_home(get_str(path, "mounts")?);
+                self.mounts.push((prefix.clone(), path));
+            }
+        }
+        if let Some(index) = value.get("index") {
+            if let Some(v) = index.get("fields") {
+                let fields = v.as_array()
+                    .ok_or_else(|| String::from("index.fields shall be an array"))?;
+                self.index_fields = vec![];
+                for f in fields {
+                    self.index_fields.push(get_str(f, "index.fields")?.to_string());
+                }
+            }
+        }
+        if let Some(git) = value.get("git") {
+            if let Some(v) = git.get("backend") {
+                let backend = get_str(v, "git.backend")?;
+                if backend != GIT_BACKEND_LIBGIT2 && backend != GIT_BACKEND_COMMAND {
+                    return Err(format!("git.backend shall be \"{}\" or \"{}\"",
+                                       GIT_BACKEND_LIBGIT2, GIT_BACKEND_COMMAND));
+                }
+                self.git_backend = backend.to_string();
+            }
+            if let Some(v) = git.get("message") {
+                self.commit_message = get_str(v, "git.message")?.to_string();
+            }
+            if let Some(v) = git.get("commit_signers") {
+                let keys = v.as_array().ok_or_else(|| {
+                    String::from("git.commit_signers shall be an array")
+                })?;
+                self.commit_signers = vec![];
+                for k in keys {
+                    self.commit_signers
+                        .push(get_str(k, "git.commit_signers")?.to_string());
+                }
+            }
+            if let Some(v) = git.get("allowed_files") {
+                let files = v.as_array().ok_or_else(|| {
+                    String::from("git.allowed_files shall be an array")
+                })?;
+                self.allowed_files = vec![];
+                for f in files {
+                    self.allowed_files
+                        .push(get_str(f, "git.allowed_files")?.to_string());
+                }
+            }
+        }
+        if let Some(generator) = value.get("generator") {
+            if let Some(v) = generator.get("length") {
+                self.generate_length = get_int(v, "generator.length")? as usize;
+            }
+            if let Some(v) = generator.get("symbols") {
+                self.generate_symbols = get_bool(v, "generator.symbols")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a mounted store to the user config file, an existing mount with
+    /// the same `prefix` is replaced.
+    pub fn add_mount(prefix: &str, path: &Path) -> Result<()> {
+        let path = match path.to_str() {
+            Some(p) => p.to_string(),
+            None => {
+                let s = format!("Invalid mount location: {:?}", path);
+                return Err(ConfigError::Parse(s));
+            }
+        };
+
+        Config::update_user_config(|table| {
+            let mounts = table.entry(String::from("mounts"))
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+            match *mounts {
+                toml::Value::Table(ref mut mounts) => {
+                    mounts.insert(prefix.to_string(), toml::Value::String(path));
+                    Ok(())
+                },
+                _ => Err(ConfigError::Parse(String::from("mounts shall be a table"))),
+            }
+        })
+    }
+
+    /// Removes a mounted store from the user config file. Returns `false` if
+    /// no mount with the given `prefix` exists.
+    pub fn remove_mount(prefix: &str) -> Result<bool> {
+        Config::update_user_config(|table| {
+            match table.get_mut("mounts") {
+                Some(&mut toml::Value::Table(ref mut mounts)) =>
+                    Ok(mounts.remove(prefix).is_some()),
+                _ => Ok(false),
+            }
+        })
+    }
+
+    /// Reads the user config file, lets `f` modify it and writes it back.
+    fn update_user_config<F, R>(f: F) -> Result<R>
+        where F: FnOnce(&mut toml::value::Table) -> Result<R>
+    {
+        let path = match Config::user_config_file() {
+            Some(p) => p,
+            None => {
+                let s = String::from("Unable to locate user config file");
+                return Err(ConfigError::Parse(s));
+            }
+        };
+
+        let mut table = toml::value::Table::new();
+        if path.is_file() {
+            let mut content = String::new();
+            File::open(&path)?.read_to_string(&mut content)?;
+            match content.parse::<toml::Value>() {
+                Ok(toml::Value::Table(t)) => table = t,
+                Ok(_) => (),
+                Err(e) => {
+                    let s = format!("{:?}: {}", path, e);
+                    return Err(ConfigError::Parse(s));
+                },
+            }
+        }
+
+        let result = f(&mut table)?;
+
+        let content = toml::to_string(&toml::Value::Table(table))
+            .map_err(|e| ConfigError::Parse(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        File::create(&path)?.write_all(content.as_bytes())?;
+
+        Ok(result)
+    }
+
+    fn merge_env(&mut self) -> Result<()> {
+        if let Ok(val) = env::var(STORE_DIR_ENV_NAME) {
+            self.store = PathBuf::from(val);
+        }
+        if let Ok(val) = env::var(CLIP_TIME_ENV_NAME) {
+            self.clip_time = val.parse().map_err(|_| {
+                ConfigError::Parse(format!("invalid number of seconds in env \
+                                            {}: {}", CLIP_TIME_ENV_NAME, val))
+            })?;
+        }
+        if let Ok(val) = env::var(GENERATED_LENGTH_ENV_NAME) {
+            self.generate_length = val.parse().map_err(|_| {
+                ConfigError::Parse(format!("invalid length in env {}: {}",
+                                           GENERATED_LENGTH_ENV_NAME, val))
+            })?;
+        }
+        if let Ok(val) = env::var(SIGNING_KEY_ENV_NAME) {
+            self.signing_keys = val.split_whitespace().map(String::from).collect();
+        }
+        if let Ok(val) = env::var(EDITOR_ENV_NAME) {
+            if !val.is_empty() {
+                self.editor = val;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_str<'a>(value: &'a toml::Value, key: &str) -> result::Result<&'a str, String> {
+    value.as_str().ok_or_else(|| format!("{} shall be a string", key))
+}
+
+fn get_int(value: &toml::Value, key: &str) -> result::Result<i64, String> {
+    match value.as_integer() {
+        Some(i) if i >= 0 => Ok(i),
+        _ => Err(format!("{} shall be a positive integer", key)),
+    }
+}
+
+fn get_bool(value: &toml::Value, key: &str) -> result::Result<bool, String> {
+    value.as_bool().ok_or_else(|| format!("{} shall be a boolean", key))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = env::home_dir() {
+            return home.join(rest);
+        }
+    }
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+
+    #[test]
+    fn test_merge_str() {
+        let mut config = Config::default();
+        config.merge_str("clip_time = 10\n\
+                          searcher = \"ag\"\n\
+                          auto_push = true\n\
+                          vcs = \"none\"\n\
+                          [generator]\n\
+                          length = 42\n\
+                          [git]\n\
+                          backend = \"command\"\n\
+                          message = \"[team] {message}\"\n\
+                          commit_signers = [\"ABCD 1234\"]\n\
+                          [index]\n\
+                          fields = [\"user\", \"tags\"]\n\
+                          [mounts]\n\
+                          team = \"/stores/team\"\n").unwrap();
+
+        assert_eq!(config.clip_time, 10);
+        assert_eq!(config.searcher, "ag");
+        assert_eq!(config.auto_push, true);
+        assert_eq!(config.generate_length, 42);
+        assert_eq!(config.editor, "vim");
+        assert_eq!(config.mounts.len(), 1);
+        assert_eq!(config.mounts[0].0, "team");
+        assert_eq!(config.index_fields, vec!["user", "tags"]);
+        assert_eq!(config.git_backend, "command");
+        assert_eq!(config.commit_message, "[team] {message}");
+        assert_eq!(config.commit_signers, vec!["ABCD 1234"]);
+        assert_eq!(config.vcs, "none");
+
+        assert!(config.merge_str("clip_time = \"ten\"").is_err());
+        assert!(config.merge_str("[git]\nbackend = \"svn\"").is_err());
+    }
+
+    #[test]
+    fn test_keep_trusted() {
+        let mut config = Config::default();
+        config.merge_str("searcher = \"ag\"").unwrap();
+        let trusted = config.clone();
+
+        // settings of the store config which must not be trusted
+        config.merge_str("clip_time = 10\n\
+                          editor = \"evil\"\n\
+                          searcher = \"evil\"\n\
+                          auto_push = true\n\
+                          vcs = \"none\"\n\
+                          lock_timeout = 0\n\
+                          [git]\n\
+                          backend = \"command\"\n\
+                          commit_signers = [\"ABCD 1234\"]\n\
+                          allowed_files = [\"*\"]\n\
+                          [mounts]\n\
+                          team = \"/stores/team\"\n").unwrap();
+        config.keep_trusted(trusted);
+
+        assert_eq!(config.clip_time, 10);
+        assert_eq!(config.editor, "vim");
+        assert_eq!(config.searcher, "ag");
+        assert_eq!(config.auto_push, false);
+        assert_eq!(config.vcs, "auto");
+        assert_eq!(config.lock_timeout, 10);
+        assert_eq!(config.git_backend, "libgit2");
+        assert!(config.commit_signers.is_empty());
+        assert!(config.allowed_files.is_empty());
+        assert!(config.mounts.is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+//! A line based diff of two versions of an entry, used to show the changes of
+//! the history. Secret lines (the password and fields like `pin:`) can be
+//! masked, so that a diff does not reveal them. Also a three-way merge of two
+//! versions with their common ancestor, used to resolve conflicts on sync.
+
+use std::cmp;
+use std::fmt;
+
+/// Parts of field names which mark a field as secret.
+static SECRET_FIELDS: &'static [&'static str] =
+    &["pass", "secret", "token", "pin", "key", "otp"];
+
+/// Replacement of masked lines.
+pub static MASK: &'static str = "********";
+
+/// A line of the diff, with the line number within its version.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<'a> {
+    Same(usize, &'a str),
+    Removed(usize, &'a str),
+    Added(usize, &'a str),
+}
+
+impl<'a> Change<'a> {
+    /// Formats the change like `Display`, but the values of secret lines are
+    /// replaced by `MASK`.
+    pub fn to_masked_string(&self) -> String {
+        match *self {
+            Change::Same(i, l) => format!("  {}", mask(i, l)),
+            Change::Removed(i, l) => format!("- {}", mask(i, l)),
+            Change::Added(i, l) => format!("+ {}", mask(i, l)),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Change<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Same(_, l) => write!(f, "  {}", l),
+            Change::Removed(_, l) => write!(f, "- {}", l),
+            Change::Added(_, l) => write!(f, "+ {}", l),
+        }
+    }
+}
+
+/// Returns whether line `index` of an entry is secret, i.e. the password
+/// (first line) or a field whose name contains one of `SECRET_FIELDS`.
+pub fn is_secret(index: usize, line: &str) -> bool {
+    if index == 0 {
+        return true;
+    }
+    match line.find(':') {
+        Some(i) => {
+            let key = line[..i].trim().to_lowercase();
+            SECRET_FIELDS.iter().any(|f| key.contains(f))
+        },
+        None => false,
+    }
+}
+
+/// Masks the value of a secret line, field names are kept.
+fn mask(index: usize, line: &str) -> String {
+    if !is_secret(index, line) {
+        line.to_string()
+    } else if index == 0 {
+        MASK.to_string()
+    } else {
+        let i = line.find(':').unwrap();
+        format!("{}: {}", &line[..i], MASK)
+    }
+}
+
+/// Computes the changes from `old` to `new` line by line, based on the longest
+/// common subsequence. Entries are small, hence the quadratic table is fine.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the length of the common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            result.push(Change::Same(j, b[j]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(Change::Removed(i, a[i]));
+            i += 1;
+        } else {
+            result.push(Change::Added(j, b[j]));
+            j += 1;
+        }
+    }
+    result.extend((i..a.len()).map(|i| Change::Removed(i, a[i])));
+    result.extend((j..b.len()).map(|j| Change::Added(j, b[j])));
+    result
+}
+
+/// Markers of the local and the remote side of a conflict in the result of
+/// `merge3`.
+pub static CONFLICT_START: &'static str = "<<<<<<< local";
+pub static CONFLICT_SEPARATOR: &'static str = "=======";
+pub static CONFLICT_END: &'static str = ">>>>>>> remote";
+
+/// Merges the changes from `base` to `local` and from `base` to `remote` line
+/// by line. Returns the merged text, or the text with conflict markers around
+/// the lines which were changed differently on both sides.
+pub fn merge3(base: &str, local: &str, remote: &str) -> Result<String, String> {
+    let o: Vec<&str> = base.lines().collect();
+    let a: Vec<&str> = local.lines().collect();
+    let b: Vec<&str> = remote.lines().collect();
+    let in_a = matching_lines(&o, &a);
+    let in_b = matching_lines(&o, &b);
+
+    let mut result = vec![];
+    let mut conflict = false;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // the next base line which is unchanged on both sides, or the end
+        let next = (i..o.len())
+            .filter_map(|m| match (in_a[m], in_b[m]) {
+                (Some(ma), Some(mb)) if ma >= j && mb >= k => Some((m, ma, mb)),
+                _ => None,
+            })
+            .next();
+        let (m, ma, mb) = next.unwrap_or((o.len(), a.len(), b.len()));
+
+        let (chunk_o, chunk_a, chunk_b) = (&o[i..m], &a[j..ma], &b[k..mb]);
+        if chunk_a == chunk_o || chunk_a == chunk_b {
+            result.extend_from_slice(chunk_b);
+        } else if chunk_b == chunk_o {
+            result.extend_from_slice(chunk_a);
+        } else {
+            conflict = true;
+            result.push(CONFLICT_START);
+            result.extend_from_slice(chunk_a);
+            result.push(CONFLICT_SEPARATOR);
+            result.extend_from_slice(chunk_b);
+            result.push(CONFLICT_END);
+        }
+
+        if m == o.len() {
+            break;
+        }
+        result.push(o[m]);
+        i = m + 1;
+        j = ma + 1;
+        k = mb + 1;
+    }
+
+    let mut merged = result.join("\n");
+    if !merged.is_empty() {
+        merged.push('\n');
+    }
+    if conflict { Err(merged) } else { Ok(merged) }
+}
+
+/// Returns for each line of `a` the line of `b` it is matched with by the
+/// longest common subsequence.
+fn matching_lines(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![None; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            result[i] = Some(j);
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff_lines, merge3, Change, MASK};
+
+    #[test]
+    fn test_diff_lines() {
+        let old = "old-pw\nuser: alice\npin: 1234\nurl: a.example.com\n";
+        let new = "new-pw\nuser: alice\npin: 1234\nurl: b.example.com\n";
+        let changes = diff_lines(old, new);
+
+        assert_eq!(changes, vec![
+            Change::Removed(0, "old-pw"),
+            Change::Added(0, "new-pw"),
+            Change::Same(1, "user: alice"),
+            Change::Same(2, "pin: 1234"),
+            Change::Removed(3, "url: a.example.com"),
+            Change::Added(3, "url: b.example.com"),
+        ]);
+
+        let masked: Vec<String> = changes.iter()
+            .map(|c| c.to_masked_string())
+            .collect();
+        assert_eq!(masked[0], format!("- {}", MASK));
+        assert_eq!(masked[3], format!("  pin: {}", MASK));
+        assert_eq!(masked[5], "+ url: b.example.com");
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base = "pw\nuser: alice\nurl: a.example.com\n";
+        let local = "pw\nuser: alice\nurl: b.example.com\n";
+        let remote = "new-pw\nuser: alice\nurl: a.example.com\n";
+        assert_eq!(merge3(base, local, remote),
+                   Ok(String::from("new-pw\nuser: alice\nurl: b.example.com\n")));
+        assert_eq!(merge3(base, local, local), Ok(String::from(local)));
+
+        // changes of the same or adjacent lines conflict
+        let remote = "pw\nuser: alice\nurl: c.example.com\n";
+        assert_eq!(merge3(base, local, remote),
+                   Err(String::from("pw\nuser: alice\n<<<<<<< local\n\
+                                     url: b.example.com\n=======\n\
+                                     url: c.example.com\n>>>>>>> remote\n")));
+    }
+}
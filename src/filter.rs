@@ -0,0 +1,133 @@
+//! Filters which match entries of the store by their metadata fields.
+//!
+//! The first line of an entry is the password, all following lines of the form
+//! `key: value` are fields of the entry. The field `tags` holds a comma or
+//! whitespace separated list of tags.
+
+use std::fmt;
+
+use ::secret::Secret;
+
+pub static TAGS_FIELD: &'static str = "tags";
+
+/// A filter for entries, all filters passed to a query have to match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The field (case insensitive) has a value which matches the glob
+    /// pattern, where `*` matches any sequence and `?` any single character.
+    Field(String, String),
+    /// The entry is tagged with the tag (case insensitive).
+    Tag(String),
+}
+
+impl Filter {
+    /// Parses a field filter of the form `key=pattern`.
+    pub fn parse_field(s: &str) -> Result<Filter, String> {
+        match s.find('=') {
+            Some(i) if i > 0 => Ok(Filter::Field(s[..i].trim().to_lowercase(),
+                                                 s[i+1..].to_string())),
+            _ => Err(format!("Invalid field filter '{}', expected key=pattern.",
+                             s)),
+        }
+    }
+
+    /// Returns whether the filter matches the given (parsed) fields.
+    pub fn matches(&self, fields: &[(String, String)]) -> bool {
+        match *self {
+            Filter::Field(ref key, ref pattern) => fields.iter()
+                .any(|(k, v)| k == key && glob_match(pattern, v)),
+            Filter::Tag(ref tag) => fields.iter()
+                .filter(|(k, _)| k == TAGS_FIELD)
+                .flat_map(|(_, v)| v.split(|c: char| {
+                    c == ',' || c.is_whitespace()
+                }))
+                .any(|t| t.eq_ignore_ascii_case(tag)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Filter::Field(ref k, ref p) => write!(f, "{}={}", k, p),
+            Filter::Tag(ref t) => write!(f, "tag {}", t),
+        }
+    }
+}
+
+/// Parses the fields of decrypted entry `content`. The keys are lower case,
+/// keys and values are trimmed. The password (first line) is never part of the
+/// result.
+pub fn parse_fields(content: &Secret) -> Vec<(String, String)> {
+    let content = String::from_utf8_lossy(content.as_bytes());
+    content.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let i = line.find(':')?;
+            let key = line[..i].trim();
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return None;
+            }
+            Some((key.to_lowercase(), line[i+1..].trim().to_string()))
+        })
+        .collect()
+}
+
+/// Matches `text` against the glob `pattern`, supporting `*` and `?`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // position of the last `*` in the pattern and the text position it
+    // matched up to, used for backtracking
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, parse_fields, Filter};
+    use ::secret::Secret;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.example.com", "https://www.example.com"));
+        assert!(glob_match("db?", "db1"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.example.com", "example.com"));
+        assert!(!glob_match("db?", "db"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let content = Secret::from(String::from(
+            "url: secret\nUser: alice\nurl: https://db.example.com\n\
+             tags: prod, db\nnot a field\n"));
+        let fields = parse_fields(&content);
+        assert_eq!(fields.len(), 3);
+
+        let f = |s| Filter::parse_field(s).unwrap();
+        assert!(f("user=alice").matches(&fields));
+        assert!(f("URL=*.example.com").matches(&fields));
+        assert!(!f("url=secret").matches(&fields));
+        assert!(Filter::Tag("PROD".to_string()).matches(&fields));
+        assert!(!Filter::Tag("staging".to_string()).matches(&fields));
+        assert!(Filter::parse_field("=foo").is_err());
+    }
+}
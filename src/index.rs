@@ -0,0 +1,190 @@
+//! An optional search index of the store, which maps entries to selected
+//! metadata fields (see `filter`), so that queries do not have to decrypt every
+//! entry.
+//!
+//! The index is stored as toml in a single file of the store, encrypted to the
+//! recipient of the store root. Entries with a different recipient are never
+//! indexed, otherwise their fields would be readable by the recipient of the
+//! store root. Passwords (the first line of an entry) are never indexed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use toml;
+
+use ::filter;
+use ::secret::Secret;
+
+pub static INDEX_FILE: &'static str = ".rass-index.gpg";
+
+/// Indexed data of an entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    /// Modification time of the gpg-file when it was indexed, used to detect
+    /// changes made by other tools.
+    pub modified: String,
+    /// The indexed fields.
+    pub fields: Vec<(String, String)>,
+}
+
+/// The search index, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    fields: Vec<String>,
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+impl Index {
+    /// Creates an empty index of the given fields.
+    pub fn new(fields: &[String]) -> Index {
+        Index {
+            fields: fields.iter().map(|f| f.to_lowercase()).collect(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Parses the decrypted index file.
+    pub fn parse(content: &Secret) -> Result<Index, String> {
+        let content = content.as_str()
+            .ok_or_else(|| String::from("index is not valid UTF-8"))?;
+        let value = content.parse::<toml::Value>().map_err(|e| e.to_string())?;
+
+        let fields = value.get("fields")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| String::from("index has no fields"))?
+            .iter()
+            .filter_map(|f| f.as_str().map(String::from))
+            .collect::<Vec<_>>();
+        let mut index = Index::new(&fields);
+
+        let entries = match value.get("entries").and_then(|v| v.as_table()) {
+            Some(entries) => entries,
+            None => return Ok(index),
+        };
+        for (name, e) in entries {
+            let modified = e.get("modified").and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            let mut fields = vec![];
+            if let Some(values) = e.get("fields").and_then(|v| v.as_array()) {
+                for kv in values {
+                    let kv = kv.as_array().map(|a| {
+                        a.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>()
+                    });
+                    if let Some([k, v]) = kv.as_ref().map(|x| &x[..]) {
+                        fields.push((k.to_string(), v.to_string()));
+                    }
+                }
+            }
+            index.entries.insert(name.clone(),
+                                 IndexEntry { modified: modified, fields: fields });
+        }
+
+        Ok(index)
+    }
+
+    /// Serializes the index, the result is meant to be encrypted.
+    pub fn to_secret(&self) -> Secret {
+        let mut entries = toml::value::Table::new();
+        for (name, e) in &self.entries {
+            let mut t = toml::value::Table::new();
+            t.insert(String::from("modified"),
+                     toml::Value::String(e.modified.clone()));
+            let fields = e.fields.iter()
+                .map(|(k, v)| toml::Value::Array(vec![
+                    toml::Value::String(k.clone()),
+                    toml::Value::String(v.clone())]))
+                .collect();
+            t.insert(String::from("fields"), toml::Value::Array(fields));
+            entries.insert(name.clone(), toml::Value::Table(t));
+        }
+
+        let mut root = toml::value::Table::new();
+        root.insert(String::from("fields"), toml::Value::Array(
+            self.fields.iter().map(|f| toml::Value::String(f.clone())).collect()));
+        root.insert(String::from("entries"), toml::Value::Table(entries));
+
+        Secret::from(toml::to_string(&toml::Value::Table(root))
+            .unwrap_or_default())
+    }
+
+    /// Returns the indexed fields.
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Returns the indexed data of `entry`, if it is indexed.
+    pub fn get(&self, entry: &str) -> Option<&IndexEntry> {
+        self.entries.get(entry)
+    }
+
+    /// Returns the names of all indexed entries.
+    pub fn entries(&self) -> Vec<&str> {
+        self.entries.keys().map(|x| x.as_str()).collect()
+    }
+
+    /// Indexes the decrypted `content` of `entry`, stored in the gpg-file at
+    /// `path`. Only the fields of the index are kept.
+    pub fn update(&mut self, entry: &str, path: &Path, content: &Secret) {
+        let fields = filter::parse_fields(content)
+            .into_iter()
+            .filter(|(k, _)| self.fields.contains(k))
+            .collect();
+        self.entries.insert(entry.to_string(), IndexEntry {
+            modified: modified(path),
+            fields: fields,
+        });
+    }
+
+    /// Removes `entry` from the index. Returns `false` if it was not indexed.
+    pub fn remove(&mut self, entry: &str) -> bool {
+        self.entries.remove(entry).is_some()
+    }
+
+    /// Returns whether the indexed data of `entry` is up to date with its
+    /// gpg-file at `path`.
+    pub fn is_current(&self, entry: &str, path: &Path) -> bool {
+        match self.entries.get(entry) {
+            Some(e) => e.modified == modified(path),
+            None => false,
+        }
+    }
+}
+
+/// Returns the modification time of the file at `path` as string.
+fn modified(path: &Path) -> String {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| format!("{}.{:09}", d.as_secs(), d.subsec_nanos()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::Index;
+    use ::secret::Secret;
+
+    #[test]
+    fn test_index() {
+        let mut index = Index::new(&["user".to_string(), "Tags".to_string()]);
+        let content = Secret::from(String::from(
+            "password\nuser: alice\nurl: example.com\ntags: prod\n"));
+        index.update("team/db", Path::new("/nonexistent"), &content);
+
+        assert_eq!(index.get("team/db").unwrap().fields,
+                   vec![("user".to_string(), "alice".to_string()),
+                        ("tags".to_string(), "prod".to_string())]);
+
+        let parsed = Index::parse(&index.to_secret()).unwrap();
+        assert_eq!(parsed, index);
+
+        assert_eq!(index.entries(), vec!["team/db"]);
+        assert!(index.remove("team/db"));
+        assert!(!index.remove("team/db"));
+    }
+}
@@ -1,8 +1,23 @@
+extern crate git2;
 extern crate gpgme;
+extern crate libc;
+extern crate tempfile;
+extern crate toml;
 
+pub mod config;
+pub mod diff;
+pub mod filter;
+pub mod index;
+pub mod lock;
+pub mod ops;
+pub mod recording;
+pub mod secret;
 pub mod store;
+pub mod template;
 pub mod vcs;
 
 mod util;
+#[cfg(test)]
+mod testutil;
 pub mod tree;
 
@@ -0,0 +1,103 @@
+//! Advisory locking of a password store. Concurrent rass processes which
+//! modify the same store (e.g. parallel `insert`s from scripts) would otherwise
+//! race on writing files and on committing to the version control.
+//!
+//! The lock is an `flock(2)` on a lock file in the directory of the store's
+//! repository (e.g. `.git`), or within the store if it has no repository. It
+//! is released by the kernel if the process dies while holding it.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+pub static LOCK_FILE: &'static str = ".rass.lock";
+
+/// Interval in which a held lock is retried.
+const RETRY_INTERVAL_MS: u64 = 50;
+
+/// An acquired lock of a store. The lock is released on drop.
+#[derive(Debug)]
+pub struct StoreLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl StoreLock {
+    /// Acquires the lock for the store in `dir`. If the lock is held by
+    /// another process, it is retried until `timeout` has elapsed. Fails with
+    /// `io::ErrorKind::WouldBlock` if the lock could not be acquired in time.
+    pub fn acquire(dir: &Path, timeout: Duration) -> io::Result<StoreLock> {
+        let path = dir.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let start = Instant::now();
+        loop {
+            let r = unsafe {
+                libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+            };
+            if r == 0 {
+                return Ok(StoreLock { file: file, path: path });
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+            if start.elapsed() >= timeout {
+                let s = format!("{:?} is held by another process", path);
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, s));
+            }
+            thread::sleep(Duration::from_millis(RETRY_INTERVAL_MS));
+        }
+    }
+
+    /// Returns the location of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::time::Duration;
+    use super::StoreLock;
+
+    #[test]
+    fn test_acquire() {
+        let dir = env::temp_dir()
+            .join(format!("rass-test-lock-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        {
+            let _lock = StoreLock::acquire(&dir, Duration::from_secs(0)).unwrap();
+            let err = StoreLock::acquire(&dir, Duration::from_millis(100))
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        }
+        assert!(StoreLock::acquire(&dir, Duration::from_secs(0)).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,36 +3,65 @@ extern crate rasslib;
 extern crate clap;
 extern crate clipboard;
 extern crate fork;
+extern crate libc;
 extern crate rpassword;
-extern crate tempfile;
 
+use std::cmp;
+use std::env;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::env;
-use std::path::PathBuf;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::thread;
+use std::time;
 use std::time::Duration;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 use clipboard::{ClipboardContext, ClipboardProvider};
 use fork::{daemon, Fork};
 
-use rasslib::store::PassStore;
+use rasslib::config::{self, Config};
+use rasslib::diff;
+use rasslib::ops::Operations;
+use rasslib::filter::Filter;
+use rasslib::secret::{self, Secret};
+use rasslib::store::{EntryConflict, FsckIssue, PassStore, PassStoreError,
+                     Resolution};
 use rasslib::vcs;
 
-use tempfile::NamedTempFile;
+fn main() {
+    secret::disable_core_dumps();
 
-static STORE_DIR_ENV_NAME: &'static str = "PASSWORD_STORE_DIR";
-static CLIP_TIME_ENV_NAME: &'static str = "PASSWORD_STORE_CLIP_TIME";
+    let matches = get_matches();
 
-fn main() {
-    let store = match env::var(STORE_DIR_ENV_NAME) {
-        Ok(val) => {
-            let p = PathBuf::from(val);
-            PassStore::from(&p)
-        },
-        Err(_)  => PassStore::new(),
+    let mut config = match Config::load() {
+        Ok(c) => c,
+        Err(e) =>
+        {
+            println!("Error loading config {}", e);
+            return
+        }
+    };
+
+    if matches.is_present("no-color") {
+        config.color = false;
+    }
+
+    // commands which address single entries do not need to walk the store
+    let lazy = match matches.subcommand_name() {
+        Some("edit") | Some("insert") | Some("add") | Some("clip") |
+        Some("show") | Some("git") | Some("hg") | Some("rm") | Some("init") |
+        Some("undo") | Some("log") | Some("restore") | Some("diff") |
+        Some("sync") => true,
+        Some(_) => false,
+        None => matches.is_present("PASS"),
+    };
+    let store = if lazy {
+        PassStore::from_config_lazy(&config)
+    } else {
+        PassStore::from_config(&config)
     };
     let store = match store {
         Ok(s) => s,
@@ -43,17 +72,40 @@ fn main() {
         }
     };
 
+    // the repository is only created by these commands
+    let init_vcs = match matches.subcommand() {
+        ("init", Some(m)) => m.is_present("git"),
+        ("git", Some(m)) => is_git_init(m),
+        _ => false,
+    };
+    let vcs = match vcs::from_config(&config) {
+        Ok(vcs) => vcs,
+        Err(_) if init_vcs => Box::new(vcs::NoVcs{}),
+        Err(e) => {
+            eprintln!("{}, changes are not committed. Set vcs = \"none\" in \
+                       the config to disable version control.", e);
+            Box::new(vcs::NoVcs{})
+        }
+    };
+
     let mut app = PassstoreApp {
-        vcs: vcs::from_path(&store.get_location()),
+        vcs: vcs,
         store: store,
+        config: config,
     };
 
-    let matches = get_matches();
-
     if matches.is_present("verbose") {
         app.store.set_verbose(true);
     }
 
+    let ops = if matches.is_present("dry-run") {
+        Operations::dry_run()
+    } else {
+        Operations::new()
+    };
+    app.store.set_operations(ops.clone());
+    app.vcs.set_operations(ops.clone());
+
     let ran_subcommand = match matches.subcommand() {
         ("edit", Some(matches)) =>   { app.edit(&matches); true }
         ("find", Some(matches)) =>   { app.find(&matches); true }
@@ -63,9 +115,18 @@ fn main() {
         ("show", Some(matches)) =>   { app.show(&matches, false); true }
         ("ls", Some(matches)) =>     { app.list(&matches); true }
         ("git", Some(matches)) =>    { app.git_exec(&matches); true }
+        ("hg", Some(matches)) =>     { app.hg_exec(&matches); true }
         ("rm", Some(matches)) =>     { app.remove(&matches); true }
         ("grep", Some(matches)) =>   { app.grep(&matches); true }
         ("init", Some(matches)) =>   { app.init(&matches); true }
+        ("fsck", Some(matches)) =>   { app.fsck(&matches); true }
+        ("mounts", Some(matches)) => { app.mounts(&matches); true }
+        ("index", Some(matches)) =>  { app.index(&matches); true }
+        ("undo", Some(matches)) =>   { app.undo(&matches); true }
+        ("sync", Some(matches)) =>   { app.sync(&matches); true }
+        ("log", Some(matches)) =>    { app.log(&matches); true }
+        ("restore", Some(matches)) => { app.restore(&matches); true }
+        ("diff", Some(matches)) =>   { app.diff(&matches); true }
         _ => false
     };
 
@@ -77,24 +138,61 @@ fn main() {
             app.list(&matches);
         }
     }
+
+    if ops.is_dry_run() {
+        let plan = ops.plan();
+        println!("Dry run, {} planned operations:", plan.len());
+        for op in plan {
+            println!("  {}", op);
+        }
+    }
 }
 
 struct PassstoreApp {
     store: PassStore,
-    vcs: Box<vcs::VersionControl>
+    vcs: Box<vcs::VersionControl>,
+    config: Config,
 }
 
 impl PassstoreApp {
-    fn git_exec(&self, matches: &ArgMatches) {
+    fn git_exec(&mut self, matches: &ArgMatches) {
         if !matches.is_present("PARAMS") {
             println!("Not git parameters found!");
             process::exit(-1);
         }
 
         let params: Vec<_> = matches.values_of("PARAMS").unwrap().collect();
+        if is_git_init(matches) {
+            self.init_git();
+            return;
+        }
+        if params == ["install-hook"] {
+            self.install_hook();
+            return;
+        }
+        self.dispatch("git", params);
+    }
+
+    fn hg_exec(&self, matches: &ArgMatches) {
+        let params: Vec<_> = matches.values_of("PARAMS").unwrap().collect();
+        self.dispatch("hg", params);
+    }
 
-        if let Ok(r) = self.vcs.cmd_dispatch(params) {
-            process::exit(r.code().unwrap_or(-1))
+    /// Runs `program` of the version control with `params` within the store,
+    /// exits with the status of a failed command.
+    fn dispatch(&self, program: &str, params: Vec<&str>) {
+        if self.vcs.program().is_some_and(|p| p != program) {
+            eprintln!("The store is not within a {} repository.", program);
+            process::exit(-1);
+        }
+        match self.vcs.cmd_dispatch(params) {
+            Ok(()) => (),
+            Err(vcs::VcsError::Command(_, status, _)) =>
+                process::exit(status.code().unwrap_or(-1)),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(-1);
+            }
         }
     }
 
@@ -116,26 +214,60 @@ impl PassstoreApp {
         let multiline = matches.is_present("multiline");
 
         let stdin = io::stdin();
-        let mut buffer = vec![];
 
-        if multiline {
+        // a template for a new entry is filled in with the editor, unless the
+        // content is piped in
+        let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 };
+        let template = if multiline && interactive && self.store.get(pass).is_none() {
+            self.template_for(pass)
+        } else {
+            None
+        };
+
+        let buffer = if let Some(content) = template {
+            match edit_in_tempfile(pass, &content, &self.config.editor) {
+                Some(content) => content,
+                None => process::exit(1),
+            }
+        } else if multiline {
             println!("Enter contents for {} and press Ctrl+D when finsihed:\n", pass);
+            let mut buffer = vec![];
             match stdin.lock().read_to_end(&mut buffer) {
                 Ok(..) => (),
                 Err(err) => panic!("Something went wrong: {}", err)
             }
+            Secret::new(buffer)
         } else {
-            let pw = single_line_password(pass);
-            buffer = pw.into_bytes();
-            buffer.push('\n' as u8);
-        }
+            single_line_password(pass)
+        };
 
-        match self.store.insert(&self.vcs, pass, buffer) {
+        match self.store.insert(&self.vcs, pass, &buffer) {
             Ok(_) => (),
             Err(err) => panic!("{}", err)
         }
     }
 
+    /// Returns the rendered template for the new entry `pass`, if there is
+    /// one.
+    fn template_for(&self, pass: &str) -> Option<Secret> {
+        let template = match self.store.template_for(pass) {
+            Ok(Some(t)) => t,
+            Ok(None) => return None,
+            Err(err) => {
+                println!("Error: {}", err);
+                process::exit(1);
+            }
+        };
+        match template.render(pass, self.config.generate_length,
+                              self.config.generate_symbols) {
+            Ok(content) => Some(content),
+            Err(err) => {
+                println!("Error: Unable to render template: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
     fn list(&self, matches: &ArgMatches) {
         let pass = matches.value_of("PASS").unwrap_or_default();
 
@@ -152,19 +284,37 @@ impl PassstoreApp {
         }
     }
 
-    fn show(&self, matches: &ArgMatches, clip: bool) {
+    fn show(&mut self, matches: &ArgMatches, clip: bool) {
         let pass = matches.value_of("PASS").unwrap_or("");
+        if let Some(rev) = matches.value_of("rev") {
+            match self.store.read_rev(&self.vcs, pass, rev) {
+                Ok(x) => if clip || matches.is_present("clip") {
+                        copy_clipboard(&x, &self.config)
+                    } else {
+                        print_secret(&x)
+                    },
+                Err(err) => {
+                    println!("Error: Unable to read {} at {}: {}", pass, rev, err);
+                    process::exit(1);
+                }
+            }
+            return
+        }
         if let Some(entry) = self.store.get(pass) {
             if entry.is_leaf() {
                 match self.store.read(&entry) {
                     Some(x) => if clip || matches.is_present("clip") {
-                            copy_clipboard(&x)
+                            copy_clipboard(&x, &self.config)
                         } else if !clip {
-                            print!("{}", x)
+                            print_secret(&x)
                         },
                     None => println!("Unable to read!"),
                 }
             } else {
+                if let Err(err) = self.store.load() {
+                    println!("Error: {}", err);
+                    process::exit(1);
+                }
                 self.store.print_tree(&entry);
             }
         } else {
@@ -173,19 +323,43 @@ impl PassstoreApp {
     }
 
     fn find(&self, matches: &ArgMatches) {
-        let query = matches.value_of("QUERY").unwrap();
+        let query = matches.value_of("QUERY").unwrap_or("");
         let print = matches.is_present("print");
+
+        let mut filters = vec![];
+        for f in matches.values_of("field").into_iter().flat_map(|x| x) {
+            match Filter::parse_field(f) {
+                Ok(f) => filters.push(f),
+                Err(err) => {
+                    println!("Error: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        for t in matches.values_of("tag").into_iter().flat_map(|x| x) {
+            filters.push(Filter::Tag(t.to_string()));
+        }
+
         //let matches = match matches.is_present("name") {
             //true => self.store.find_by_name(query),
             //_    => self. store.find_by_location(query),
         //};
-        let matches = self.store.find(query);
+        let tree = matches.is_present("tree");
+        let matches = self.store.find_with(query, &filters);
+
+        if tree {
+            if !matches.is_empty() {
+                self.store.print_paths(&matches);
+            }
+            return
+        }
 
         if matches.len() == 1 {
             let e = &matches[0];
             println!("Only found: '{}'", e);
             if let Some(x) =  self.store.read(e) {
-                println!("{}", x);
+                print_secret(&x);
+                println!();
                 return
             } else {
                 println!("Unable to read!");
@@ -195,7 +369,11 @@ impl PassstoreApp {
         for e in matches {
             if print {
                 match self.store.read(&e) {
-                    Some(x) => println!("{}:\n{}", e, x),
+                    Some(x) => {
+                        println!("{}:", e);
+                        print_secret(&x);
+                        println!();
+                    },
                     None => println!("Unable to read!"),
                 }
             }
@@ -228,7 +406,8 @@ impl PassstoreApp {
             process::exit(-1);
         }
 
-        let searcher = matches.value_of("SEACHER").unwrap_or("grep");
+        let searcher = matches.value_of("SEARCHER")
+            .unwrap_or(&self.config.searcher);
         if let Ok(out) = self.store.grep(&searcher, &params) {
             println!("{}", out);
         }
@@ -236,19 +415,31 @@ impl PassstoreApp {
 
     fn edit(&mut self, matches: &ArgMatches) {
         let pass = matches.value_of("PASS").unwrap_or("");
-        if let Some(entry) = self.store.get(pass) {
-            if let Some(content) = self.store.read(&entry) {
-                if let Some(content) = edit_in_tempfile(&content) {
-                    match self.store.insert(&self.vcs, pass, content) {
-                        Ok(_) => (),
-                        Err(err) => panic!("{}", err)
-                    }
+        let content = match self.store.get(pass) {
+            Some(entry) => match self.store.read(&entry) {
+                Some(content) => content,
+                None => {
+                    println!("Error: Unable to read {}.", entry);
+                    process::exit(1);
                 }
-            }
-            else { println!("Error: Unable to read {}.", entry); }
-            //let _ = self.store.remove(vcs, &entry);
-        } else {
-            println!("Error: {} is not in the password store.", pass);
+            },
+            None => self.template_for(pass)
+                .unwrap_or_else(|| Secret::new(Vec::new())),
+        };
+
+        let edited = match edit_in_tempfile(pass, &content, &self.config.editor) {
+            Some(edited) => edited,
+            None => process::exit(1),
+        };
+
+        if edited.as_bytes() == content.as_bytes() {
+            println!("Password for {} unchanged.", pass);
+            return
+        }
+
+        if let Err(err) = self.store.edit(&self.vcs, pass, &edited) {
+            println!("Error: {}", err);
+            process::exit(1);
         }
     }
 
@@ -260,6 +451,222 @@ impl PassstoreApp {
         }
 
         println!("Password store initialized for {}.", gpgid);
+
+        if matches.is_present("git") {
+            self.init_git();
+        }
+    }
+
+    /// Creates the git repository of the store with the diff driver for
+    /// gpg-files and commits the `.gpg-id`.
+    fn init_git(&mut self) {
+        let ops = self.store.operations().clone();
+        let result = vcs::init(&self.config, ops)
+            .map_err(PassStoreError::from)
+            .and_then(|vcs| {
+                self.store.commit_init(&vcs)?;
+                self.vcs = vcs;
+                Ok(())
+            });
+        if let Err(err) = result {
+            eprintln!("Unable to initialize git: {}", err);
+            process::exit(1);
+        }
+        println!("Initialized git repository, `rass git log -p` shows \
+                  decrypted changes.");
+    }
+
+    /// Installs the pre-commit hook which refuses unencrypted files.
+    fn install_hook(&self) {
+        match vcs::install_hook(&self.config, self.store.operations()) {
+            Ok(path) => println!("Installed pre-commit hook {:?}.", path),
+            Err(err) => {
+                eprintln!("Unable to install the pre-commit hook: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn mounts(&self, matches: &ArgMatches) {
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let prefix = matches.value_of("PREFIX").unwrap();
+                let path = PathBuf::from(matches.value_of("PATH").unwrap());
+                if !path.is_dir() {
+                    println!("Error: {:?} is not a directory.", path);
+                    process::exit(1);
+                }
+                if let Err(err) = Config::add_mount(prefix, &path) {
+                    println!("Error: {}", err);
+                    process::exit(1);
+                }
+                println!("Mounted {:?} as {}.", path, prefix);
+            },
+            ("rm", Some(matches)) => {
+                let prefix = matches.value_of("PREFIX").unwrap();
+                match Config::remove_mount(prefix) {
+                    Ok(true) => println!("Unmounted {}.", prefix),
+                    Ok(false) => {
+                        println!("Error: {} is not mounted.", prefix);
+                        process::exit(1);
+                    },
+                    Err(err) => {
+                        println!("Error: {}", err);
+                        process::exit(1);
+                    },
+                }
+            },
+            _ => {
+                for (prefix, location) in self.store.mounts() {
+                    println!("{} => {}", prefix, location);
+                }
+            },
+        }
+    }
+
+    fn fsck(&self, _matches: &ArgMatches) {
+        let issues = self.store.fsck();
+        for issue in &issues {
+            println!("{}", issue);
+        }
+
+        // an outdated search index is fixed right away
+        let (drift, issues): (Vec<_>, Vec<_>) = issues
+            .into_iter()
+            .partition(|i| if let FsckIssue::IndexDrift(..) = *i { true } else { false });
+        if !drift.is_empty() {
+            println!("Rebuilding the search index, the same as `rass index rebuild`.");
+            self.rebuild_index();
+        }
+
+        if !issues.is_empty() {
+            process::exit(1);
+        }
+    }
+
+    fn log(&self, matches: &ArgMatches) {
+        let pass = matches.value_of("PASS").unwrap();
+        let max = match value_t!(matches, "max-count", usize) {
+            Ok(max) => max,
+            Err(err) => err.exit(),
+        };
+
+        match self.store.history(&self.vcs, pass, max) {
+            Ok(ref commits) if commits.is_empty() =>
+                println!("No history found for {}.", pass),
+            Ok(commits) => for c in commits {
+                println!("{} {} {} {}", &c.id[..cmp::min(8, c.id.len())],
+                         c.date, c.author, c.subject());
+            },
+            Err(err) => {
+                println!("Error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn restore(&mut self, matches: &ArgMatches) {
+        let pass = matches.value_of("PASS").unwrap();
+        let rev = matches.value_of("rev").unwrap();
+
+        if let Err(err) = self.store.restore(&self.vcs, pass, rev) {
+            println!("Error: Unable to restore {} from {}: {}", pass, rev, err);
+            process::exit(1);
+        }
+        println!("Restored {} from {}.", pass, rev);
+    }
+
+    fn diff(&self, matches: &ArgMatches) {
+        let pass = matches.value_of("PASS").unwrap();
+        let reveal = matches.is_present("reveal");
+
+        // without a revision, the version before the last change is used
+        let rev = match matches.value_of("REV") {
+            Some(rev) => Some(rev.to_string()),
+            None => match self.store.history(&self.vcs, pass, 2) {
+                Ok(ref commits) if commits.len() > 1 => Some(commits[1].id.clone()),
+                Ok(_) => None,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    process::exit(1);
+                }
+            },
+        };
+
+        let old = match rev {
+            Some(ref rev) => match self.store.read_rev(&self.vcs, pass, rev) {
+                Ok(old) => old,
+                Err(err) => {
+                    println!("Error: Unable to read {} at {}: {}", pass, rev, err);
+                    process::exit(1);
+                }
+            },
+            None => Secret::new(vec![]),
+        };
+        let new = match self.store.get(pass) {
+            Some(ref entry) if entry.is_leaf() => match self.store.read(entry) {
+                Some(new) => new,
+                None => process::exit(1),
+            },
+            _ => Secret::new(vec![]),
+        };
+
+        let (old_str, new_str) = match (old.as_str(), new.as_str()) {
+            (Some(o), Some(n)) => (o, n),
+            _ => {
+                println!("Error: {} is not a text entry.", pass);
+                process::exit(1);
+            }
+        };
+
+        println!("--- {}@{}", pass, rev.as_ref().map(|r| r.as_str()).unwrap_or("(none)"));
+        println!("+++ {}", pass);
+        for change in diff::diff_lines(old_str, new_str) {
+            if reveal {
+                println!("{}", change);
+            } else {
+                println!("{}", change.to_masked_string());
+            }
+        }
+    }
+
+    fn sync(&mut self, _matches: &ArgMatches) {
+        let editor = self.config.editor.clone();
+        match self.store.sync(&self.vcs, |c| resolve_conflict(c, &editor)) {
+            Ok(0) => println!("Store synced."),
+            Ok(n) => println!("Store synced, {} conflicts resolved.", n),
+            Err(err) => {
+                println!("Error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn undo(&mut self, matches: &ArgMatches) {
+        match self.store.undo(&self.vcs, matches.is_present("force")) {
+            Ok(subject) => println!("Reverted: {}", subject),
+            Err(err) => {
+                println!("Error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn index(&self, matches: &ArgMatches) {
+        match matches.subcommand() {
+            ("rebuild", Some(_)) => self.rebuild_index(),
+            _ => println!("{}", matches.usage()),
+        }
+    }
+
+    fn rebuild_index(&self) {
+        match self.store.rebuild_index(&self.vcs) {
+            Ok(count) => println!("Search index rebuilt, {} entries indexed.", count),
+            Err(err) => {
+                println!("Error: Unable to rebuild the search index: {}", err);
+                process::exit(1);
+            }
+        }
     }
 }
 
@@ -280,6 +687,13 @@ fn get_matches<'a>() -> ArgMatches<'a> {
              .help("Print verbose information during execution.")
              .long("verbose")
              .short("v"))
+        .arg(Arg::with_name("no-color")
+             .help("Do not colorize the output.")
+             .long("no-color"))
+        .arg(Arg::with_name("dry-run")
+             .help("Print the file changes and version control commands of \
+                    the store instead of executing them.")
+             .long("dry-run"))
         .subcommand(SubCommand::with_name("find")
                     .about("Query a pass store entry")
                     .arg(Arg::with_name("print")
@@ -288,8 +702,25 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                          .help("Immediately print all results"))
                     .arg(Arg::with_name("QUERY")
                          .help("Query string use for the find command")
-                         .required(true)
                          .index(1))
+                    .arg(Arg::with_name("field")
+                         .short("f")
+                         .long("field")
+                         .value_name("KEY=PATTERN")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Only entries with field KEY matching the \
+                                glob PATTERN, e.g. url=*.example.com"))
+                    .arg(Arg::with_name("tag")
+                         .short("t")
+                         .long("tag")
+                         .value_name("TAG")
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Only entries tagged with TAG (field 'tags:')"))
+                    .arg(Arg::with_name("tree")
+                         .long("tree")
+                         .help("Print the results as tree"))
                     .arg(Arg::with_name("name")
                          .short("n")
                          .long("name")
@@ -312,6 +743,11 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                          .short("c")
                          .long("clip")
                          .help("Copy the password to the clipboard also"))
+                    .arg(Arg::with_name("rev")
+                         .long("rev")
+                         .value_name("REV")
+                         .help("Show the entry as of the given revision, \
+                                see 'rass log'"))
                     .arg(Arg::with_name("PASS")
                         .help("PASS which shall be shown, first try \
                                pass-name (full path), if nothing is found, I'll\
@@ -345,7 +781,7 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                          .required(false)
                          .index(1)))
         .subcommand(SubCommand::with_name("edit")
-                    .about("Edit a given entry.")
+                    .about("Edit a given entry, creates it if it does not exist.")
                     .arg(Arg::with_name("PASS")
                          .help("Entry which shall be edited, first try \
                                 pass-name (full path), if nothing is found, I'll\
@@ -365,21 +801,30 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                          .long("force")
                          .help("Forces to delete an entry, without interaction.")))
         .subcommand(SubCommand::with_name("git")
-                    .about("Dispatch git command to execute within the store")
+                    .about("Dispatch git command to execute within the store. \
+                            `init` creates the repository with the diff driver \
+                            for gpg-files, `install-hook` installs a pre-commit \
+                            hook which refuses unencrypted files.")
+                    .arg(Arg::with_name("PARAMS")
+                         .multiple(true)
+                         .required(true)))
+        .subcommand(SubCommand::with_name("hg")
+                    .about("Dispatch hg command to execute within the store")
                     .arg(Arg::with_name("PARAMS")
                          .multiple(true)
                          .required(true)))
         .subcommand(SubCommand::with_name("grep")
                     .about("Greps for given search term in the password store. \
                           Relays the all parameter (except searcher) to to the \
-                          command specified in SEACHER parameter, default \
-                          'grep'. Therefore standard grep options apply.")
+                          command specified in SEARCHER parameter, default \
+                          'grep' or as configured. Therefore standard grep \
+                          options apply.")
                     .arg(Arg::with_name("SEARCHER")
                          .possible_values(&["ag", "grep", "ack"])
                          .short("s")
                          .long("searcher")
-                         .required(false)
-                         .default_value("grep"))
+                         .takes_value(true)
+                         .required(false))
                     .arg(Arg::with_name("PARAMS")
                          .multiple(true)
                          .required(true)))
@@ -389,53 +834,153 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                          .help("identifier for gpg key to use for encryption, can \
                                be either of key id/fingerprint, or user id")
                          .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("git")
+                         .long("git")
+                         .help("Also creates a git repository for the store, \
+                               like `rass git init`.")))
+        .subcommand(SubCommand::with_name("mounts")
+                    .about("Manage stores which are mounted into the password \
+                            store under a prefix. Lists all mounts if no \
+                            subcommand is given.")
+                    .subcommand(SubCommand::with_name("add")
+                                .about("Mount the store at PATH as PREFIX.")
+                                .arg(Arg::with_name("PREFIX")
+                                     .required(true)
+                                     .index(1))
+                                .arg(Arg::with_name("PATH")
+                                     .required(true)
+                                     .index(2)))
+                    .subcommand(SubCommand::with_name("rm")
+                                .about("Unmount the store mounted as PREFIX.")
+                                .arg(Arg::with_name("PREFIX")
+                                     .required(true)
+                                     .index(1)))
+                    .subcommand(SubCommand::with_name("ls")
+                                .about("List all mounted stores.")))
+        .subcommand(SubCommand::with_name("fsck")
+                    .about("Check the password store for inconsistencies, \
+                            e.g. unsigned or invalid signed .gpg-id files. \
+                            An outdated search index is rebuilt."))
+        .subcommand(SubCommand::with_name("log")
+                    .about("List the commits which changed an entry.")
+                    .arg(Arg::with_name("max-count")
+                         .short("n")
+                         .long("max-count")
+                         .value_name("NUM")
+                         .default_value("20")
+                         .help("Show at most NUM commits"))
+                    .arg(Arg::with_name("PASS")
+                         .required(true)
                          .index(1)))
+        .subcommand(SubCommand::with_name("restore")
+                    .about("Restore an entry as of an old revision, as a new \
+                            commit.")
+                    .arg(Arg::with_name("rev")
+                         .long("rev")
+                         .value_name("REV")
+                         .required(true)
+                         .help("Revision to restore, see 'rass log'"))
+                    .arg(Arg::with_name("PASS")
+                         .required(true)
+                         .index(1)))
+        .subcommand(SubCommand::with_name("diff")
+                    .about("Show the changes of an entry since a revision, \
+                            by default since the version before the last \
+                            change. Passwords are masked.")
+                    .arg(Arg::with_name("reveal")
+                         .long("reveal")
+                         .help("Do not mask passwords"))
+                    .arg(Arg::with_name("PASS")
+                         .required(true)
+                         .index(1))
+                    .arg(Arg::with_name("REV")
+                         .index(2)))
+        .subcommand(SubCommand::with_name("sync")
+                    .about("Pull the remote changes with rebase, resolve \
+                            conflicting entries and push."))
+        .subcommand(SubCommand::with_name("undo")
+                    .about("Revert the last commit made by rass.")
+                    .arg(Arg::with_name("force")
+                         .short("f")
+                         .long("force")
+                         .help("Also if there are newer commits not made by \
+                                rass.")))
+        .subcommand(SubCommand::with_name("index")
+                    .about("Manage the search index, see index.fields in the \
+                            config.")
+                    .subcommand(SubCommand::with_name("rebuild")
+                                .about("Rebuild the search index by \
+                                        decrypting all entries.")))
         .get_matches()
 }
 
-fn single_line_password(pass: &str) -> String {
+/// Returns whether the git command is `git init` without further arguments,
+/// which is run by rass itself, see `PassstoreApp::init_git`.
+fn is_git_init(matches: &ArgMatches) -> bool {
+    matches.values_of("PARAMS")
+        .map(|p| p.collect::<Vec<_>>() == ["init"])
+        .unwrap_or(false)
+}
+
+/// Reads a password twice from the terminal and returns it including a
+/// trailing newline.
+fn single_line_password(pass: &str) -> Secret {
     let mut stdout = std::io::stdout();
     loop {
         print!("Enter password for {}: ", pass);
         stdout.flush().unwrap();
-        let password = rpassword::read_password().unwrap();
+        let password = Secret::from(rpassword::read_password().unwrap());
 
         print!("Confirm password for {}: ", pass);
         stdout.flush().unwrap();
-        let password_confirm = rpassword::read_password().unwrap();
-        if password != password_confirm {
+        let password_confirm = Secret::from(rpassword::read_password().unwrap());
+        if password.as_bytes() != password_confirm.as_bytes() {
             println!("Error: the entered passwords do not match.");
         } else {
-            return password.to_string();
+            let mut buffer = Vec::with_capacity(password.len() + 1);
+            buffer.extend_from_slice(password.as_bytes());
+            buffer.push(b'\n');
+            return Secret::new(buffer);
         }
     }
 }
 
-fn copy_clipboard(s: &str) {
+fn print_secret(s: &Secret) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(s.as_bytes());
+    let _ = stdout.flush();
+}
+
+fn copy_clipboard(s: &Secret, config: &Config) {
     if s.is_empty() {
         eprintln!("empty password");
         return
     }
 
-    let clip_secs = match env::var(CLIP_TIME_ENV_NAME) {
-        Ok(val) => match val.parse() {
-            Ok(secs) => secs,
-            Err(_) => {
-                eprintln!("invalid number of seconds in env {}: {}", CLIP_TIME_ENV_NAME, val);
-                45
-            }
-        },
-        Err(_) => 45,
-    };
+    let clip_secs = config.clip_time;
 
-    let pass = s[..s.len() - 1].to_string();
+    // the clipboard takes ownership of the content, hence this copy cannot be
+    // wiped.
+    let pass = String::from_utf8_lossy(&s.as_bytes()[..s.len() - 1]).into_owned();
     match daemon(false, false) {
         Ok(Fork::Child) => {
-            let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-            ctx.set_contents(pass.clone()).unwrap();
-            thread::sleep(Duration::from_secs(clip_secs));
-            if ctx.get_contents().unwrap() == pass {
-                ctx.set_contents("".to_string()).unwrap()
+            if config.clipboard == config::CLIPBOARD_SYSTEM {
+                let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
+                ctx.set_contents(pass.clone()).unwrap();
+                thread::sleep(Duration::from_secs(clip_secs));
+                if ctx.get_contents().unwrap() == pass {
+                    ctx.set_contents("".to_string()).unwrap()
+                }
+            } else {
+                clipboard_command(&config.clipboard, &pass);
+                thread::sleep(Duration::from_secs(clip_secs));
+                let copied = paste_command(&config.clipboard)
+                    .and_then(|paste| clipboard_contents(&paste));
+                match copied {
+                    Some(ref content) if *content != pass => (),
+                    _ => clipboard_command(&config.clipboard, ""),
+                }
             }
             process::exit(0)
         },
@@ -444,6 +989,63 @@ fn copy_clipboard(s: &str) {
     }
 }
 
+/// Copies `content` to the clipboard by passing it to the stdin of the given
+/// clipboard `command`, e.g. `xclip -selection clipboard`.
+fn clipboard_command(command: &str, content: &str) {
+    let mut parts = command.split_whitespace();
+    let program = match parts.next() {
+        Some(p) => p,
+        None => return,
+    };
+
+    let child = process::Command::new(program)
+        .args(parts)
+        .stdin(process::Stdio::piped())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(content.as_bytes());
+            }
+            let _ = child.wait();
+        },
+        Err(err) => eprintln!("unable to spawn {}: {}", program, err),
+    }
+}
+
+/// Returns the command which prints the clipboard filled by the clipboard
+/// `command`, if it is one of `wl-copy`, `xclip`, `xsel` or `pbcopy`.
+fn paste_command(command: &str) -> Option<String> {
+    let mut parts = command.split_whitespace();
+    let program = parts.next()?;
+    let name = Path::new(program).file_name()?.to_str()?;
+    // the directory of the program, including the trailing slash
+    let dir = &program[..program.len() - name.len()];
+    match name {
+        "wl-copy" => {
+            let primary = parts.any(|a| a == "-p" || a == "--primary");
+            let flags = if primary { " --primary" } else { "" };
+            Some(format!("{}wl-paste --no-newline{}", dir, flags))
+        },
+        "xclip" | "xsel" => Some(format!("{} -o", command)),
+        "pbcopy" => Some(format!("{}pbpaste", dir)),
+        _ => None,
+    }
+}
+
+/// Returns the clipboard printed by the `paste` command, `None` if it fails.
+fn clipboard_contents(paste: &str) -> Option<String> {
+    let mut parts = paste.split_whitespace();
+    let output = process::Command::new(parts.next()?)
+        .args(parts)
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let content = String::from_utf8_lossy(&output.stdout);
+    Some(content.trim_end_matches('\n').to_string())
+}
+
 #[derive(Debug)]
 enum YesNoAnswer {
     YES,
@@ -466,12 +1068,204 @@ fn  yes_no(message: &str, default: YesNoAnswer) -> YesNoAnswer {
     }
 }
 
-fn edit_in_tempfile(content: &str) -> Option<String> {
-    let mut file = NamedTempFile::new().unwrap();
-    let _ = write!(file, "{}\n", &content);
+/// Directory used for temporary files while editing, it should be on tmpfs.
+static SECURE_TMPDIR: &'static str = "/dev/shm";
+
+/// A private (mode 0700) temporary directory. On drop all files in the
+/// directory are overwritten before the directory is removed.
+struct SecureTmpDir {
+    path: PathBuf,
+}
+
+impl SecureTmpDir {
+    fn new() -> io::Result<SecureTmpDir> {
+        SecureTmpDir::within(Path::new(SECURE_TMPDIR))
+    }
+
+    /// Creates the directory in `shm`, or in the temporary directory of the
+    /// system if `shm` does not exist.
+    fn within(shm: &Path) -> io::Result<SecureTmpDir> {
+        let base = if shm.is_dir() {
+            shm.to_path_buf()
+        } else {
+            let tmp = env::temp_dir();
+            eprintln!("Warning: {:?} is not available, the decrypted content \
+                       is written to {:?} which might be on disk.",
+                      shm, tmp);
+            tmp
+        };
 
-    match process::Command::new(env::var("EDITOR").unwrap_or("vim".to_string()))
-        .arg(file.path().to_str().unwrap()).status() {
+        let nanos = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut last_err = None;
+        for i in 0..10 {
+            let path = base.join(format!("rass.{}.{}.{}", process::id(), nanos, i));
+            match fs::DirBuilder::new().mode(0o700).create(&path) {
+                Ok(_) => return Ok(SecureTmpDir { path: path }),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SecureTmpDir {
+    fn drop(&mut self) {
+        if let Ok(rd) = fs::read_dir(&self.path) {
+            for entry in rd {
+                if let Ok(entry) = entry {
+                    shred_file(&entry.path());
+                }
+            }
+        }
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Overwrites the content of the file at `path` with zeros and removes it.
+fn shred_file(path: &Path) {
+    if let Ok(mut f) = fs::OpenOptions::new().write(true).open(path) {
+        if let Ok(m) = f.metadata() {
+            let _ = f.write_all(&vec![0; m.len() as usize]);
+            let _ = f.sync_all();
+        }
+    }
+    let _ = fs::remove_file(path);
+}
+
+/// Returns the command to start `editor` for `file`. For known editors, swap,
+/// backup and undo files are disabled, since those would hold the decrypted
+/// content outside of the temporary directory.
+fn editor_command(editor: &str, file: &Path) -> Option<process::Command> {
+    let mut parts = editor.split_whitespace();
+    let program = parts.next()?;
+
+    let mut cmd = process::Command::new(program);
+    cmd.args(parts);
+
+    let name = Path::new(program).file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    match name {
+        "vi" | "vim" | "nvim" | "gvim" => {
+            cmd.arg("-n")
+               .arg("-i").arg("NONE")
+               .arg("--cmd").arg("set nobackup nowritebackup noundofile");
+        },
+        "emacs" | "emacsclient" => {
+            cmd.arg("--eval")
+               .arg("(setq make-backup-files nil auto-save-default nil \
+                     create-lockfiles nil)");
+        },
+        _ => (),
+    }
+
+    cmd.arg(file);
+    Some(cmd)
+}
+
+/// Asks how to resolve the conflict of an entry on sync. Changes which do not
+/// overlap are merged, otherwise the local or the remote version can be kept
+/// or the merge with conflict markers can be edited.
+fn resolve_conflict(c: &EntryConflict, editor: &str) -> Resolution {
+    println!("{} was changed locally and on the remote.", c.entry);
+
+    fn text(s: &Option<Secret>) -> Option<&str> {
+        s.as_ref().and_then(|s| s.as_str())
+    }
+    let merged = match (text(&c.local), text(&c.remote)) {
+        (Some(local), Some(remote)) => {
+            let base = text(&c.base).unwrap_or("");
+            match diff::merge3(base, local, remote) {
+                Ok(merged) => {
+                    let q = "The changes do not overlap. Keep both? [Y/n]";
+                    if let YesNoAnswer::YES = yes_no(q, YesNoAnswer::YES) {
+                        return Resolution::Merged(Secret::from(merged));
+                    }
+                    Some(merged)
+                },
+                Err(conflicting) => Some(conflicting),
+            }
+        },
+        _ => None,
+    };
+
+    let local = if c.local.is_some() { "keep [l]ocal" } else { "[l]ocal removal" };
+    let remote = if c.remote.is_some() { "[r]emote" } else { "[r]emote removal" };
+    loop {
+        if merged.is_some() {
+            print!("Choose {}, {} version, [e]dit the merge or [a]bort: ",
+                   local, remote);
+        } else {
+            print!("Choose {}, {} version or [a]bort: ", local, remote);
+        }
+        io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            return Resolution::Abort;
+        }
+        match (input.trim(), merged.as_ref()) {
+            ("l", _) => return Resolution::Local,
+            ("r", _) => return Resolution::Remote,
+            ("a", _) => return Resolution::Abort,
+            ("e", Some(merged)) => {
+                let content = Secret::from(merged.clone());
+                match edit_in_tempfile(&c.entry, &content, editor) {
+                    Some(ref edited) if edited.as_str()
+                        .map_or(false, |e| e.contains(diff::CONFLICT_START)) =>
+                        println!("The result still contains conflict markers."),
+                    Some(edited) => return Resolution::Merged(edited),
+                    None => (),
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn edit_in_tempfile(pass: &str, content: &Secret, editor: &str) -> Option<Secret> {
+    let dir = match SecureTmpDir::new() {
+        Ok(dir) => dir,
+        Err(e) => {
+            println!("Unable to create temporary directory: {}", e);
+            return None
+        }
+    };
+    let path = dir.path().join(format!("{}.txt", pass.replace('/', "-")));
+
+    {
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("Unable to create temporary file: {}", e);
+                return None
+            }
+        };
+        if let Err(e) = file.write_all(content.as_bytes()) {
+            println!("Unable to write temporary file: {}", e);
+            return None
+        }
+    }
+
+    let mut cmd = match editor_command(editor, &path) {
+        Some(cmd) => cmd,
+        None => {
+            println!("No editor configured!");
+            return None
+        }
+    };
+    match cmd.status() {
         Err(e) => {
             println!("Error occured: '{:?}'", e);
             return None
@@ -482,12 +1276,98 @@ fn edit_in_tempfile(content: &str) -> Option<String> {
         }
     }
 
-    let mut f = io::BufReader::new(file.try_clone().unwrap());
-    let _ = f.seek(io::SeekFrom::Start(0));
-    let mut result = String::new();
+    // the editor might have replaced the file, hence open it again
+    let mut f = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(_) => return None,
+    };
+    let len = match f.metadata() {
+        Ok(m) => m.len() as usize,
+        Err(_) => return None,
+    };
+    let mut result = Secret::new(vec![0; len]);
 
-    match f.read_to_string(&mut result) {
+    match f.read_exact(result.as_mut_bytes()) {
         Ok(_) => Some(result),
         Err(_) => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use rasslib::secret::Secret;
+    use super::{edit_in_tempfile, editor_command, paste_command,
+                SecureTmpDir};
+
+    #[test]
+    fn test_secure_tmp_dir() {
+        let dir = SecureTmpDir::within(&env::temp_dir()).unwrap();
+        let path = dir.path().to_path_buf();
+        assert!(path.starts_with(env::temp_dir()));
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        fs::write(path.join("entry.txt"), "secret").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+
+        // without tmpfs the temporary directory of the system is used
+        let dir = SecureTmpDir::within(Path::new("/nonexistent/shm")).unwrap();
+        assert!(dir.path().starts_with(env::temp_dir()));
+    }
+
+    #[test]
+    fn test_paste_command() {
+        assert_eq!(paste_command("wl-copy").unwrap(), "wl-paste --no-newline");
+        assert_eq!(paste_command("/usr/bin/wl-copy --primary").unwrap(),
+                   "/usr/bin/wl-paste --no-newline --primary");
+        assert_eq!(paste_command("xclip -selection clipboard").unwrap(),
+                   "xclip -selection clipboard -o");
+        assert_eq!(paste_command("xsel -b").unwrap(), "xsel -b -o");
+        assert_eq!(paste_command("pbcopy").unwrap(), "pbpaste");
+        assert!(paste_command("my-clipboard --copy").is_none());
+        assert!(paste_command("").is_none());
+    }
+
+    #[test]
+    fn test_editor_command() {
+        let file = Path::new("/dev/shm/rass/entry.txt");
+        let args = |editor: &str| {
+            let cmd = editor_command(editor, file).unwrap();
+            let mut args = vec![cmd.get_program().to_string_lossy().into_owned()];
+            args.extend(cmd.get_args().map(|a| a.to_string_lossy().into_owned()));
+            args
+        };
+
+        assert_eq!(args("code --wait"), ["code", "--wait", "/dev/shm/rass/entry.txt"]);
+        assert_eq!(args("/usr/bin/vim -u NONE"),
+                   ["/usr/bin/vim", "-u", "NONE", "-n", "-i", "NONE", "--cmd",
+                    "set nobackup nowritebackup noundofile",
+                    "/dev/shm/rass/entry.txt"]);
+        assert!(editor_command("  ", file).is_none());
+    }
+
+    #[test]
+    fn test_edit_in_tempfile() {
+        // the content is returned as is if the editor does not change it,
+        // hence `edit` commits nothing
+        let content = Secret::from(String::from("old\nuser: alice"));
+        let edited = edit_in_tempfile("web/mail", &content, "true").unwrap();
+        assert_eq!(edited.as_bytes(), content.as_bytes());
+
+        let edited = edit_in_tempfile("web/mail", &content, "sed -i s/old/new/")
+            .unwrap();
+        assert_eq!(edited.as_bytes(), b"new\nuser: alice");
+
+        // a new entry is edited from scratch
+        let edited = edit_in_tempfile("web/new", &Secret::new(vec![]), "true")
+            .unwrap();
+        assert!(edited.as_bytes().is_empty());
+
+        assert!(edit_in_tempfile("web/mail", &content, "false").is_none());
+    }
+}
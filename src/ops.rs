@@ -0,0 +1,176 @@
+//! The operation layer through which the store and the version control
+//! change the file-system and run commands. In dry-run mode the operations
+//! are recorded as a plan instead of being executed, e.g. to review a bulk
+//! change before it is applied.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::rc::Rc;
+
+/// A single change of the file-system or an executed command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    CreateDir(PathBuf),
+    WriteFile(PathBuf),
+    RemoveFile(PathBuf),
+    /// A command with its arguments, run in the given directory.
+    Command(Vec<String>, Option<PathBuf>),
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::CreateDir(ref p) => write!(f, "create directory {:?}", p),
+            Operation::WriteFile(ref p) => write!(f, "write {:?}", p),
+            Operation::RemoveFile(ref p) => write!(f, "remove {:?}", p),
+            Operation::Command(ref args, ref dir) => {
+                let args: Vec<String> = args.iter().map(|a| {
+                    if a.is_empty() || a.contains(char::is_whitespace) {
+                        format!("{:?}", a)
+                    } else {
+                        a.clone()
+                    }
+                }).collect();
+                write!(f, "run `{}`", args.join(" "))?;
+                match *dir {
+                    Some(ref dir) => write!(f, " in {:?}", dir),
+                    None => Ok(()),
+                }
+            },
+        }
+    }
+}
+
+/// Executes operations or, in dry-run mode, records them. Clones share the
+/// same plan, hence the store and its version control record into one plan.
+#[derive(Debug, Clone, Default)]
+pub struct Operations {
+    plan: Option<Rc<RefCell<Vec<Operation>>>>,
+}
+
+impl Operations {
+    /// Returns operations which are executed right away.
+    pub fn new() -> Operations {
+        Operations { plan: None }
+    }
+
+    /// Returns operations which are only recorded, see `plan`.
+    pub fn dry_run() -> Operations {
+        Operations { plan: Some(Rc::new(RefCell::new(vec![]))) }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.plan.is_some()
+    }
+
+    /// Returns all operations recorded so far.
+    pub fn plan(&self) -> Vec<Operation> {
+        match self.plan {
+            Some(ref plan) => plan.borrow().clone(),
+            None => vec![],
+        }
+    }
+
+    /// Records `op` in dry-run mode, returns `false` if it has to be executed.
+    pub fn record(&self, op: Operation) -> bool {
+        match self.plan {
+            Some(ref plan) => {
+                plan.borrow_mut().push(op);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Creates `path` including all missing parents.
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        if path.is_dir() || self.record(Operation::CreateDir(path.to_path_buf())) {
+            return Ok(());
+        }
+        fs::create_dir_all(path)
+    }
+
+    /// Writes `data` to `path`, see `util::write_atomic`. Missing parent
+    /// directories are created.
+    pub fn write<R: Read>(&self, path: &Path, data: &mut R) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            self.create_dir_all(dir)?;
+        }
+        if self.record(Operation::WriteFile(path.to_path_buf())) {
+            return Ok(());
+        }
+        ::util::write_atomic(path, data)
+    }
+
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        if self.record(Operation::RemoveFile(path.to_path_buf())) {
+            return Ok(());
+        }
+        fs::remove_file(path)
+    }
+
+    /// Runs `cmd` and waits for it. In dry-run mode a successful exit status
+    /// is returned.
+    pub fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        if self.record(command(cmd)) {
+            return Ok(ExitStatus::from_raw(0));
+        }
+        cmd.status()
+    }
+
+    /// Runs `cmd` and collects its output, see `Command::output`. In dry-run
+    /// mode a successful exit status without output is returned.
+    pub fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        if self.record(command(cmd)) {
+            return Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            });
+        }
+        cmd.output()
+    }
+}
+
+fn command(cmd: &Command) -> Operation {
+    let mut args = vec![cmd.get_program().to_string_lossy().into_owned()];
+    args.extend(cmd.get_args().map(|a| a.to_string_lossy().into_owned()));
+    Operation::Command(args, cmd.get_current_dir().map(PathBuf::from))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use super::{Operation, Operations};
+
+    #[test]
+    fn test_dry_run() {
+        let ops = Operations::dry_run();
+        let shared = ops.clone();
+
+        let path = Path::new("/nonexistent/rass/foo.gpg");
+        ops.write(path, &mut "secret".as_bytes()).unwrap();
+        ops.remove_file(path).unwrap();
+        let status = shared.status(Command::new("git")
+                                   .args(&["commit", "-m", "Add foo"])
+                                   .current_dir("/nonexistent")).unwrap();
+        assert!(status.success());
+        assert!(!path.exists());
+
+        // the missing directory of the file is part of the plan
+        let plan = ops.plan();
+        assert_eq!(plan.len(), 4);
+        assert_eq!(plan[0], Operation::CreateDir(PathBuf::from("/nonexistent/rass")));
+        assert_eq!(plan[1], Operation::WriteFile(PathBuf::from(path)));
+        assert_eq!(plan[3].to_string(),
+                   "run `git commit -m \"Add foo\"` in \"/nonexistent\"");
+        assert!(Operations::new().plan().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+//! A version control which only records the calls made to it, to test code
+//! which changes a store without a real repository. Calls of chosen kinds can
+//! be made to fail, e.g. to test how a failed commit is handled.
+//!
+//! ```
+//! use rasslib::recording::{Call, CallKind, RecordingVcs};
+//! use rasslib::vcs::VersionControl;
+//!
+//! let mut recording = RecordingVcs::new();
+//! recording.fail_on(CallKind::Commit);
+//! let vcs: Box<VersionControl> = Box::new(recording.clone());
+//!
+//! vcs.add("foo.gpg").unwrap();
+//! assert!(vcs.commit("Add foo").is_err());
+//! assert_eq!(recording.calls(), vec![Call::Add(String::from("foo.gpg")),
+//!                                    Call::Commit(String::from("Add foo"))]);
+//! ```
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use ::vcs::{Result, VcsError, VersionControl};
+
+/// A call of `RecordingVcs` with its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Add(String),
+    Remove(String),
+    Commit(String),
+    Dispatch(Vec<String>),
+}
+
+impl Call {
+    pub fn kind(&self) -> CallKind {
+        match *self {
+            Call::Add(_) => CallKind::Add,
+            Call::Remove(_) => CallKind::Remove,
+            Call::Commit(_) => CallKind::Commit,
+            Call::Dispatch(_) => CallKind::Dispatch,
+        }
+    }
+}
+
+/// The kind of a `Call`, see `RecordingVcs::fail_on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Add,
+    Remove,
+    Commit,
+    Dispatch,
+}
+
+/// Records all calls of `add`, `remove`, `commit` and `cmd_dispatch`, the
+/// other operations are unsupported like for `NoVcs`. Clones share the
+/// recorded calls, hence a clone can be passed to the store while the
+/// original is inspected.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingVcs {
+    calls: Rc<RefCell<Vec<Call>>>,
+    failing: Vec<CallKind>,
+}
+
+impl RecordingVcs {
+    pub fn new() -> RecordingVcs {
+        RecordingVcs::default()
+    }
+
+    /// Makes all calls of `kind` fail, they are still recorded.
+    pub fn fail_on(&mut self, kind: CallKind) {
+        self.failing.push(kind);
+    }
+
+    /// Returns all calls recorded so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    fn record(&self, call: Call) -> Result<()> {
+        let kind = call.kind();
+        self.calls.borrow_mut().push(call);
+        if self.failing.contains(&kind) {
+            let s = format!("{:?} failed as requested", kind);
+            return Err(VcsError::Io(io::Error::other(s)));
+        }
+        Ok(())
+    }
+}
+
+impl VersionControl for RecordingVcs {
+    fn add(&self, file: &str) -> Result<()> {
+        self.record(Call::Add(file.to_string()))
+    }
+
+    fn remove(&self, file: &str) -> Result<()> {
+        self.record(Call::Remove(file.to_string()))
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        self.record(Call::Commit(message.to_string()))
+    }
+
+    fn cmd_dispatch(&self, args: Vec<&str>) -> Result<()> {
+        self.record(Call::Dispatch(args.iter().map(|a| a.to_string()).collect()))
+    }
+}
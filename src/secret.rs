@@ -0,0 +1,163 @@
+//! Contains the `Secret` type which holds decrypted content of the store.
+//!
+//! The buffer of a `Secret` is wiped on drop and, as far as permitted by the
+//! `RLIMIT_MEMLOCK` of the process, locked into memory, hence it won't be
+//! written to swap. The content is never printed through `Debug`.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::ptr;
+use std::sync::atomic;
+
+use libc;
+
+static ALPHANUMERIC: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+static SYMBOLS: &'static [u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Holds decrypted content, e.g. a password or a complete store entry.
+pub struct Secret {
+    data: Vec<u8>,
+    locked: bool,
+}
+
+impl Secret {
+    /// Constructs a new `Secret` which takes ownership of `data`. Note that
+    /// copies of `data` made before, e.g. by reallocations, are not wiped.
+    pub fn new(data: Vec<u8>) -> Secret {
+        let locked = data.capacity() > 0 && unsafe {
+            libc::mlock(data.as_ptr() as *const libc::c_void,
+                        data.capacity()) == 0
+        };
+        Secret {
+            data: data,
+            locked: locked,
+        }
+    }
+
+    /// Generates a random password of `length` characters from
+    /// `/dev/urandom`, consisting of letters and digits and, if `symbols` is
+    /// set, ASCII punctuation characters.
+    pub fn generate(length: usize, symbols: bool) -> io::Result<Secret> {
+        let mut charset = ALPHANUMERIC.to_vec();
+        if symbols {
+            charset.extend_from_slice(SYMBOLS);
+        }
+        // largest multiple of the charset size, bytes above are rejected to
+        // avoid a bias towards the first characters
+        let limit = 256 - 256 % charset.len();
+
+        let mut urandom = File::open("/dev/urandom")?;
+        let mut password = Secret::new(vec![0; length]);
+        let mut buf = Secret::new(vec![0; 64]);
+        let mut i = 0;
+        while i < length {
+            urandom.read_exact(buf.as_mut_bytes())?;
+            for &b in buf.as_bytes() {
+                if i == length {
+                    break;
+                }
+                if (b as usize) < limit {
+                    password.data[i] = charset[b as usize % charset.len()];
+                    i += 1;
+                }
+            }
+        }
+        Ok(password)
+    }
+
+    /// Returns the content of the secret.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the content of the secret for modification in place, e.g. to
+    /// read into a pre-allocated secret.
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Returns the content of the secret as `str`, if it is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        ::std::str::from_utf8(&self.data).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns whether the memory of the secret is locked, i.e. it will not
+    /// be swapped to disk.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        let cap = self.data.capacity();
+        let p = self.data.as_mut_ptr();
+        // volatile writes, so the wiping is not optimized away
+        for i in 0..cap {
+            unsafe { ptr::write_volatile(p.add(i), 0u8); }
+        }
+        atomic::compiler_fence(atomic::Ordering::SeqCst);
+
+        if self.locked {
+            unsafe { libc::munlock(p as *const libc::c_void, cap); }
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret([REDACTED {} bytes])", self.data.len())
+    }
+}
+
+impl From<Vec<u8>> for Secret {
+    fn from(data: Vec<u8>) -> Secret {
+        Secret::new(data)
+    }
+}
+
+impl From<String> for Secret {
+    fn from(data: String) -> Secret {
+        Secret::new(data.into_bytes())
+    }
+}
+
+/// Disables core dumps for the current process, so that secrets cannot end
+/// up in a core file. Returns `false` if the limit could not be set.
+pub fn disable_core_dumps() -> bool {
+    let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) == 0 }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Secret;
+
+    #[test]
+    fn test_secret() {
+        let s = Secret::from(String::from("foobar\n"));
+
+        assert_eq!(s.as_bytes(), b"foobar\n");
+        assert_eq!(s.as_str(), Some("foobar\n"));
+        assert_eq!(s.len(), 7);
+        assert_eq!(format!("{:?}", s), "Secret([REDACTED 7 bytes])");
+    }
+
+    #[test]
+    fn test_generate() {
+        let s = Secret::generate(30, false).unwrap();
+        assert_eq!(s.len(), 30);
+        assert!(s.as_bytes().iter().all(|b| b.is_ascii_alphanumeric()));
+    }
+}
@@ -1,7 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
 use std::ffi;
 use std::fmt;
+use std::cmp;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert;
 use std::error;
 use std::io;
@@ -9,10 +12,18 @@ use std::fs::File;
 use std::fs;
 use std::io::prelude::*;
 use std::result;
+use std::time::Duration;
 
 use tree;
 use gpgme;
 
+use ::config::{self, Config};
+use ::filter::{self, Filter};
+use ::index::{self, Index};
+use ::lock::StoreLock;
+use ::ops::Operations;
+use ::secret::Secret;
+use ::template::{self, Template};
 use ::vcs;
 
 macro_rules! println_stderr(
@@ -24,11 +35,16 @@ macro_rules! println_stderr(
 
 pub static PASS_ENTRY_EXTENSION: &'static str = "gpg";
 pub static PASS_GPGID_FILE: &'static str = ".gpg-id";
+pub static PASS_GPGID_SIG_FILE: &'static str = ".gpg-id.sig";
+
+/// Number of commits which `undo` searches for the last commit made by rass.
+const UNDO_SEARCH_DEPTH: usize = 100;
 
 #[derive(Debug)]
 pub enum PassStoreError {
     GPG(gpgme::Error),
     Io(io::Error),
+    Vcs(vcs::VcsError),
     Other(String),
 }
 
@@ -44,12 +60,18 @@ impl From<io::Error> for PassStoreError {
         PassStoreError::Io(err)
     }
 }
+impl From<vcs::VcsError> for PassStoreError {
+    fn from(err: vcs::VcsError) -> PassStoreError {
+        PassStoreError::Vcs(err)
+    }
+}
 
 impl fmt::Display for PassStoreError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             PassStoreError::GPG(ref err) => write!(f, "GPG error: {}", err),
             PassStoreError::Io(ref err) => write!(f, "IO error: {}", err),
+            PassStoreError::Vcs(ref err) => write!(f, "VCS error: {}", err),
             PassStoreError::Other(ref err) => write!(f, "Other error: {}", err),
         }
     }
@@ -59,6 +81,7 @@ impl error::Error for PassStoreError {
         match *self {
             PassStoreError::GPG(_) => "gpg error",
             PassStoreError::Io(ref err) => err.description(),
+            PassStoreError::Vcs(ref err) => err.description(),
             PassStoreError::Other(ref err) => err,
         }
     }
@@ -67,6 +90,7 @@ impl error::Error for PassStoreError {
         match *self {
             PassStoreError::GPG(ref err) => Some(err),
             PassStoreError::Io(ref err) => Some(err),
+            PassStoreError::Vcs(ref err) => Some(err),
             PassStoreError::Other(ref _err) => None,
         }
     }
@@ -82,8 +106,75 @@ pub type PassTreePath = tree::Path<PassEntry>;
 pub struct PassStore {
     passhome: PathBuf,
     entries: PassTree,
-    gpgid: String,
+    signing_keys: Vec<String>,
+    color: bool,
     verbose: bool,
+    lock_timeout: Duration,
+    index_fields: Vec<String>,
+    /// Template of commit messages, see `set_message_template`.
+    message_template: String,
+    mounts: Vec<Mount>,
+    /// All changes of the store are done through `ops`, see `set_operations`.
+    ops: Operations,
+    /// Whether `entries` holds the complete store, see `load`.
+    loaded: bool,
+    /// Maps the names of all entries to whether they are a leaf, see
+    /// `build_lookup_index`.
+    lookup: Option<HashMap<String, bool>>,
+    /// The running transaction, see `transaction`.
+    transaction: RefCell<Option<Transaction>>,
+}
+
+/// An entry which was changed differently by a local and a remote commit,
+/// with its decrypted versions, see `PassStore::sync`. The versions are `None`
+/// if the entry does not exist in them.
+#[derive(Debug)]
+pub struct EntryConflict {
+    pub entry: String,
+    /// The version of the common ancestor.
+    pub base: Option<Secret>,
+    pub local: Option<Secret>,
+    pub remote: Option<Secret>,
+}
+
+/// How to resolve an `EntryConflict`.
+#[derive(Debug)]
+pub enum Resolution {
+    /// Keep the given content, e.g. a merge of both versions. It is
+    /// encrypted for the current `.gpg-id` of the entry.
+    Merged(Secret),
+    Local,
+    Remote,
+    /// Abort the sync, the local commits are restored.
+    Abort,
+}
+
+/// State of a running transaction of a store, see `PassStore::transaction`.
+#[derive(Debug, Default)]
+struct Transaction {
+    /// Commit messages of the changes made so far.
+    messages: Vec<String>,
+    /// Original content of the changed files, `None` if a file did not exist.
+    backups: Vec<(PathBuf, Option<Vec<u8>>)>,
+    /// The lock of the store, held until the transaction ends.
+    lock: Option<StoreLock>,
+}
+
+/// A store which is mounted into another store under `prefix`, see
+/// `PassStore::mount`.
+struct Mount {
+    prefix: String,
+    store: PassStore,
+    vcs: Box<vcs::VersionControl>,
+}
+
+impl fmt::Debug for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mount")
+            .field("prefix", &self.prefix)
+            .field("store", &self.store)
+            .finish()
+    }
 }
 
 /// Represents the underlying directory structure of a password store.
@@ -96,14 +187,7 @@ impl PassStore {
     /// Constructs a new `PassStore` with the default store location.
     pub fn new() -> Result<PassStore> {
         let def_path = PassStore::get_default_location();
-        let mut store =  PassStore {
-            entries: PassTree::default(),
-            passhome: def_path.clone(),
-            gpgid: String::new(),
-            verbose: false,
-        };
-        try!(store.fill());
-        Ok(store)
+        PassStore::from(&def_path)
     }
 
     /// Constructs a new `PassStore` using the provided location.
@@ -120,23 +204,309 @@ impl PassStore {
     ///
     /// ```
     pub fn from(path: &PathBuf) -> Result<PassStore> {
-        let mut store =  PassStore {
+        let mut store = PassStore::unloaded(path);
+        store.load()?;
+        Ok(store)
+    }
+
+    /// Constructs a `PassStore` for `path` without walking its directory.
+    fn unloaded(path: &Path) -> PassStore {
+        PassStore {
             entries: PassTree::default(),
-            passhome: path.clone(),
-            gpgid: String::new(),
+            passhome: path.to_path_buf(),
+            signing_keys: vec![],
+            color: true,
             verbose: false,
-        };
-        try!(store.fill());
+            lock_timeout: Duration::from_secs(10),
+            index_fields: vec![],
+            message_template: String::from(config::DEFAULT_COMMIT_MESSAGE),
+            mounts: vec![],
+            ops: Operations::new(),
+            loaded: false,
+            lookup: None,
+            transaction: RefCell::new(None),
+        }
+    }
+
+    /// Constructs a new `PassStore` using the location and settings of the
+    /// given `Config`. All stores configured in `mounts` are mounted, each
+    /// with its own version control.
+    pub fn from_config(config: &Config) -> Result<PassStore> {
+        let mut store = PassStore::from_config_lazy(config)?;
+        store.load()?;
+        Ok(store)
+    }
+
+    /// Like `from_config`, but the directory of the store is not walked.
+    /// Entries are still found by `get` and can be read and modified, but
+    /// functions working on the whole tree (`entries`, `find`, `grep`,
+    /// `print_tree`, the search index) require the store to be `load`ed.
+    pub fn from_config_lazy(config: &Config) -> Result<PassStore> {
+        let mut store = PassStore::unloaded(&config.store);
+        store.set_signing_keys(config.signing_keys.clone());
+        store.set_color(config.color);
+        store.set_lock_timeout(Duration::from_secs(config.lock_timeout));
+        store.set_index_fields(config.index_fields.clone());
+        store.set_message_template(config.commit_message.clone());
+
+        for (prefix, path) in &config.mounts {
+            if !path.is_dir() {
+                let s = format!("Mount {} does not exist: {:?}", prefix, path);
+                return Err(PassStoreError::Other(s));
+            }
+            let mount_config = Config {
+                store: path.clone(),
+                mounts: vec![],
+                ..config.clone()
+            };
+            let sub = PassStore::from_config_lazy(&mount_config)?;
+            let sub_vcs = match vcs::from_config(&mount_config) {
+                Ok(vcs) => vcs,
+                Err(e) => {
+                    println_stderr!("Mount {}: {}, changes are not committed.",
+                                    prefix, e);
+                    Box::new(vcs::NoVcs{})
+                }
+            };
+            store.mount(prefix, sub, sub_vcs)?;
+        }
+
         Ok(store)
     }
 
+    /// Mounts another `store` under the given `prefix`, e.g. `team`. The
+    /// entries of the mounted store become part of `entries()`. All operations
+    /// on entries below `prefix` are routed to the mounted store, which uses
+    /// its own `.gpg-id` files and the given `vcs`.
+    pub fn mount(&mut self, prefix: &str, mut store: PassStore,
+                 mut vcs: Box<vcs::VersionControl>) -> Result<()> {
+        let prefix = prefix.trim_matches('/').to_string();
+        if prefix.is_empty() {
+            return Err(PassStoreError::Other(String::from("Empty mount prefix")));
+        }
+        if self.mounts.iter().any(|m| m.prefix == prefix) {
+            let s = format!("{} is already mounted", prefix);
+            return Err(PassStoreError::Other(s));
+        }
+
+        if self.loaded {
+            store.load()?;
+            self.graft(&prefix, &store.entries);
+            self.refresh_lookup();
+        }
+        store.set_operations(self.ops.clone());
+        vcs.set_operations(self.ops.clone());
+
+        self.mounts.push(Mount {
+            prefix: prefix,
+            store: store,
+            vcs: vcs,
+        });
+        // nested mounts have to be found first
+        self.mounts.sort_by_key(|m| cmp::Reverse(m.prefix.len()));
+
+        Ok(())
+    }
+
+    /// Grafts the `entries` of a mounted store into the own tree, missing
+    /// folders of the `prefix` are created on the way.
+    fn graft(&mut self, prefix: &str, entries: &PassTree) {
+        let mut sub = entries.clone();
+        sub.set_root(false);
+        let components: Vec<&str> = prefix.split('/').collect();
+        let (last, parents) = components.split_last().unwrap();
+        sub.name_mut().name = last.to_string();
+
+        let mut node = &mut self.entries;
+        for c in parents {
+            let entry = PassEntry { name: c.to_string() };
+            if node.sub_mut(&entry).is_none() {
+                node.add(PassTree::new(entry.clone()));
+            }
+            node = node.sub_mut(&entry).unwrap();
+        }
+        node.remove(&PassTreePath::from(vec![sub.name().clone()]));
+        node.add(sub);
+    }
+
+    /// Walks the directory of the store and of all mounted stores, if not
+    /// done yet.
+    pub fn load(&mut self) -> Result<()> {
+        if self.loaded {
+            return Ok(());
+        }
+
+        self.fill()?;
+        self.loaded = true;
+
+        // shorter prefixes first, so nested mounts are grafted on top
+        let mut mounts = ::std::mem::take(&mut self.mounts);
+        for m in mounts.iter_mut().rev() {
+            m.store.load()?;
+            self.graft(&m.prefix, &m.store.entries);
+        }
+        self.mounts = mounts;
+
+        self.refresh_lookup();
+        Ok(())
+    }
+
+    /// Walks the directory of the store again, e.g. after it was changed by
+    /// the version control. Does nothing if the store is not loaded yet.
+    pub fn reload(&mut self) -> Result<()> {
+        if !self.loaded {
+            return Ok(());
+        }
+        self.loaded = false;
+        self.load()
+    }
+
+    /// Returns whether the whole store has been walked, see `load`.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Builds a hash index of all entry names, which makes `get` a constant
+    /// time lookup. The index is kept up to date by `remove` and `mount`. Loads
+    /// the store if needed.
+    pub fn build_lookup_index(&mut self) -> Result<()> {
+        self.load()?;
+        let lookup = self.entries
+            .into_iter()
+            .map(|x| (x.to_string(), x.is_leaf()))
+            .collect();
+        self.lookup = Some(lookup);
+        Ok(())
+    }
+
+    /// Rebuilds the lookup index, if it is used.
+    fn refresh_lookup(&mut self) {
+        if self.lookup.is_some() {
+            let _ = self.build_lookup_index();
+        }
+    }
+
+    /// Returns the prefixes and locations of all mounted stores.
+    pub fn mounts(&self) -> Vec<(String, String)> {
+        self.mounts
+            .iter()
+            .map(|m| (m.prefix.clone(), m.store.get_location()))
+            .collect()
+    }
+
+    /// Returns the index of the mount which holds `entry` together with the
+    /// path of the entry within the mounted store.
+    fn mount_for(&self, entry: &str) -> Option<(usize, String)> {
+        for (i, m) in self.mounts.iter().enumerate() {
+            if entry == m.prefix {
+                return Some((i, String::new()));
+            }
+            if entry.starts_with(&m.prefix) &&
+                entry[m.prefix.len()..].starts_with('/') {
+                return Some((i, entry[m.prefix.len()+1..].to_string()));
+            }
+        }
+        None
+    }
+
     /// Set the verbose printouts for the store.
     pub fn set_verbose(&mut self, verbose: bool) {
-        self.verbose = verbose
+        self.verbose = verbose;
+        for m in &mut self.mounts {
+            m.store.set_verbose(verbose);
+        }
+    }
+
+    /// Set how long mutating operations wait for the lock of the store, if
+    /// it is held by another process.
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout
+    }
+
+    /// Acquires the lock of the store, which shall be held by every operation
+    /// which modifies the store, including the related vcs commit. Within a
+    /// transaction the lock is held by the transaction and `None` is returned.
+    /// In dry-run mode nothing is modified, hence nothing is locked.
+    fn lock(&self) -> Result<Option<StoreLock>> {
+        if self.ops.is_dry_run() {
+            return Ok(None);
+        }
+        if let Some(ref mut tx) = *self.transaction.borrow_mut() {
+            if tx.lock.is_none() {
+                tx.lock = Some(self.acquire_lock()?);
+            }
+            return Ok(None);
+        }
+        self.acquire_lock().map(Some)
+    }
+
+    fn acquire_lock(&self) -> Result<StoreLock> {
+        if self.verbose {
+            println!("Lock store: {}", self.get_location());
+        }
+        // keep the lock file out of the working tree of a repository
+        let dir = vcs::repository_dir(&self.passhome)
+            .unwrap_or_else(|| self.passhome.clone());
+        match StoreLock::acquire(&dir, self.lock_timeout) {
+            Ok(lock) => Ok(lock),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                let s = format!("Store {:?} is locked by another rass process, \
+                                 gave up after {} seconds.",
+                                self.passhome, self.lock_timeout.as_secs());
+                Err(PassStoreError::Other(s))
+            },
+            Err(err) => Err(PassStoreError::Io(err)),
+        }
+    }
+
+    /// Set whether the output of the store shall be colorized.
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color
+    }
+
+    /// Set the operation layer through which the store (and the version
+    /// control of mounted stores) changes files and runs commands. With
+    /// `Operations::dry_run` nothing is changed, but recorded as plan.
+    pub fn set_operations(&mut self, ops: Operations) {
+        for m in &mut self.mounts {
+            m.store.set_operations(ops.clone());
+            m.vcs.set_operations(ops.clone());
+        }
+        self.ops = ops;
+    }
+
+    pub fn operations(&self) -> &Operations {
+        &self.ops
+    }
+
+    /// Set the fields of the search index, the index is disabled if `fields`
+    /// is empty. See `rebuild_index`.
+    pub fn set_index_fields(&mut self, fields: Vec<String>) {
+        self.index_fields = fields.iter().map(|f| f.to_lowercase()).collect();
+    }
+
+    /// Set the template of commit messages. The placeholders `{operation}`
+    /// (e.g. `add`, `remove`), `{entry}`, `{hostname}` and `{message}`, the
+    /// default message of the operation, are replaced. The commit of a
+    /// transaction formats its summary as operation `transaction` and lists
+    /// the formatted messages of the single changes.
+    pub fn set_message_template(&mut self, template: String) {
+        self.message_template = template;
+    }
+
+    /// Set the fingerprints of the keys which are trusted to sign `.gpg-id`
+    /// files, see `PASSWORD_STORE_SIGNING_KEY` in pass(1). If any key is set,
+    /// a `.gpg-id` will only be used for encryption if its `.gpg-id.sig` holds
+    /// a valid signature of one of these keys.
+    pub fn set_signing_keys(&mut self, keys: Vec<String>) {
+        self.signing_keys = keys.iter().map(|k| ::util::normalize_fingerprint(k)).collect()
     }
 
     /// Returns the absolute_path of a given `PassEntry`.
     pub fn absolute_path(&self, entry: &str) -> PathBuf {
+        if let Some((i, sub)) = self.mount_for(entry) {
+            return self.mounts[i].store.absolute_path(&sub);
+        }
         self.passhome.clone().join(PathBuf::from(entry))
     }
 
@@ -171,19 +541,11 @@ impl PassStore {
                 };
                 let p = entry.path();
 
-                if p.ends_with(".git") {
+                if p.ends_with(".git") || p.ends_with(template::TEMPLATES_DIR) ||
+                    p.ends_with(index::INDEX_FILE) {
                     continue;
                 }
 
-                let gpgid_fname = ffi::OsStr::new(PASS_GPGID_FILE);
-                if p.file_name() == Some(gpgid_fname) {
-                    self.gpgid = match get_gpgid_from_file(&p) {
-                        Ok(id) => id,
-                        Err(_) => panic!("Unable to open file: {}",
-                                            PASS_GPGID_FILE)
-                    };
-                    continue;
-                }
                 let ending = ffi::OsStr::new(PASS_ENTRY_EXTENSION);
                 if p.is_file() && p.extension() != Some(ending) {
                     continue;
@@ -202,6 +564,7 @@ impl PassStore {
     pub fn init(&mut self, gpgid: &str) -> Result<()> {
         let ctx = gpgme::Context::from_protocol(
             gpgme::Protocol::OpenPgp).unwrap();
+        let fingerprint;
 
         if self.passhome.is_dir() {
             if let Ok(r) = fs::read_dir(self.passhome.clone()) {
@@ -222,7 +585,7 @@ impl PassStore {
                     return Err(PassStoreError::Other(s))
                 }
 
-                self.gpgid = String::from(key.fingerprint().unwrap());
+                fingerprint = String::from(key.fingerprint().unwrap());
             },
             Err(_) => {
                 let s = format!("Secret key {} not found.", gpgid);
@@ -233,19 +596,204 @@ impl PassStore {
         let gpgid_fname = String::from(PASS_GPGID_FILE);
         let gpgid_path = self.passhome.clone().join(PathBuf::from(gpgid_fname));
 
-        if let Err(_) = fs::create_dir_all(&self.passhome) {
+        if let Err(_) = self.ops.create_dir_all(&self.passhome) {
             let s = format!("Failed to create directory: {:?}", self.passhome);
             return Err(PassStoreError::Other(s))
         }
 
-        if let Err(_) = write_gpgid_to_file(&gpgid_path, &self.gpgid) {
+        if let Err(_) = write_gpgid_to_file(&self.ops, &gpgid_path, &fingerprint) {
             let s = format!("Unable to write to file: {:?}", gpgid_path);
             return Err(PassStoreError::Other(s))
         }
 
+        self.sign_gpgid(&gpgid_path)?;
+
+        Ok(())
+    }
+
+    /// Makes the initial commit of a repository created by `vcs::init`, with
+    /// the `.gpg-id` of the store root and its signature, if they exist.
+    /// Succeeds if everything is committed already.
+    pub fn commit_init(&self, vcs: &Box<vcs::VersionControl>) -> Result<()> {
+        for f in &[PASS_GPGID_FILE, PASS_GPGID_SIG_FILE] {
+            let path = self.passhome.join(f);
+            if path.is_file() {
+                vcs.add(path.to_str().unwrap())?;
+            }
+        }
+        match self.commit(vcs, "init", "", "Initialize password store.") {
+            Err(PassStoreError::Vcs(vcs::VcsError::NothingToCommit)) => Ok(()),
+            r => r,
+        }
+    }
+
+    /// Looks up the `.gpg-id` which is responsible for `entry`. As for pass(1)
+    /// the lookup starts in the folder of the entry and walks up to the root
+    /// of the store. The found file is verified before it is used.
+    fn gpgid_for(&self, entry: &str) -> Result<String> {
+        let mut dir = self.absolute_path(entry);
+
+        while dir.pop() && dir.starts_with(&self.passhome) {
+            let p = dir.join(PASS_GPGID_FILE);
+            if p.is_file() {
+                self.verify_gpgid(&p)?;
+                return get_gpgid_from_file(&p);
+            }
+        }
+
+        let s = format!("No {} found for {}.", PASS_GPGID_FILE, entry);
+        Err(PassStoreError::Other(s))
+    }
+
+    /// Returns the template for new entry `entry`. Like the `.gpg-id`, the
+    /// `.templates` directory is searched starting from the folder of the
+    /// entry up to the root of the store. In each `.templates` directory, the
+    /// template whose name is the longest path prefix of the entry (relative
+    /// to that folder) is used, `default` matches every entry.
+    pub fn template_for(&self, entry: &str) -> Result<Option<Template>> {
+        if let Some((i, sub)) = self.mount_for(entry) {
+            return self.mounts[i].store.template_for(&sub);
+        }
+
+        let mut dir = self.absolute_path(entry);
+        let name = dir.clone();
+
+        while dir.pop() && dir.starts_with(&self.passhome) {
+            let templates = dir.join(template::TEMPLATES_DIR);
+            if !templates.is_dir() {
+                continue;
+            }
+
+            let rel = name.strip_prefix(&dir).unwrap();
+            let found = rel.ancestors()
+                .filter(|p| !p.as_os_str().is_empty())
+                .map(|p| templates.join(p))
+                .find(|p| p.is_file());
+            let found = found.or_else(|| {
+                let p = templates.join(template::DEFAULT_TEMPLATE);
+                if p.is_file() { Some(p) } else { None }
+            });
+
+            if let Some(p) = found {
+                if self.verbose {
+                    println!("Using template {:?}", p);
+                }
+                return Ok(Some(Template::load(&p)?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Verifies the detached signature (`.gpg-id.sig`) of the given `.gpg-id`
+    /// file. Succeeds right away if no signing keys are configured.
+    fn verify_gpgid(&self, path: &PathBuf) -> Result<()> {
+        if self.signing_keys.is_empty() {
+            return Ok(());
+        }
+
+        let sig_path = path.with_file_name(PASS_GPGID_SIG_FILE);
+        if !sig_path.is_file() {
+            let s = format!("Signature for {:?} does not exist.", path);
+            return Err(PassStoreError::Other(s));
+        }
+
+        let mut ctx = gpgme::Context::from_protocol(
+            gpgme::Protocol::OpenPgp)?;
+        let mut signature = File::open(&sig_path)?;
+        let mut signed = File::open(path)?;
+        let result = ctx.verify_detached(&mut signature, &mut signed)?;
+
+        for sig in result.signatures() {
+            if sig.status().is_err() {
+                continue;
+            }
+            let fpr = match sig.fingerprint() {
+                Ok(fpr) => fpr,
+                Err(_) => continue,
+            };
+            // the signature might be made by a subkey, so also check the
+            // fingerprint of the primary key.
+            let primary = ctx.find_key(fpr).ok()
+                .and_then(|k| k.fingerprint().ok().map(String::from));
+            let trusted = self.signing_keys.iter().any(|k| {
+                *k == ::util::normalize_fingerprint(fpr) ||
+                    Some(k) == primary.as_ref()
+            });
+            if trusted {
+                return Ok(());
+            }
+        }
+
+        let s = format!("Signature for {:?} is invalid.", path);
+        Err(PassStoreError::Other(s))
+    }
+
+    /// Creates the detached signature (`.gpg-id.sig`) for the given `.gpg-id`
+    /// file with all configured signing keys. Does nothing if no signing keys
+    /// are configured.
+    fn sign_gpgid(&self, path: &PathBuf) -> Result<()> {
+        if self.signing_keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut ctx = gpgme::Context::from_protocol(
+            gpgme::Protocol::OpenPgp)?;
+        for k in &self.signing_keys {
+            let key = ctx.find_secret_key(&**k)?;
+            ctx.add_signer(&key)?;
+        }
+
+        let sig_path = path.with_file_name(PASS_GPGID_SIG_FILE);
+        if self.verbose {
+            println!("Going to write file: {}", sig_path.to_str().unwrap_or(""));
+        }
+        // in dry-run mode, the signed file has not been written
+        if self.ops.is_dry_run() {
+            self.ops.write(&sig_path, &mut io::empty())?;
+            return Ok(());
+        }
+        let mut input = File::open(path)?;
+        let mut output = vec![];
+        ctx.sign_detached(&mut input, &mut output)?;
+        self.ops.write(&sig_path, &mut output.as_slice())?;
+
         Ok(())
     }
 
+    /// Checks the store for inconsistencies and returns all findings. If
+    /// signing keys are configured, every `.gpg-id` in the store is expected
+    /// to have a valid signature.
+    pub fn fsck(&self) -> Vec<FsckIssue> {
+        let mut issues = vec![];
+
+        let gpgids = if self.signing_keys.is_empty() {
+            vec![]
+        } else {
+            find_gpgid_files(&self.passhome)
+        };
+        for p in gpgids {
+            let sig_path = p.with_file_name(PASS_GPGID_SIG_FILE);
+            if !sig_path.is_file() {
+                issues.push(FsckIssue::UnsignedGpgId(p));
+                continue;
+            }
+            if let Err(err) = self.verify_gpgid(&p) {
+                issues.push(FsckIssue::InvalidGpgIdSignature(p, err.to_string()));
+            }
+        }
+
+        for d in self.index_drift() {
+            issues.push(FsckIssue::IndexDrift(self.passhome.join(index::INDEX_FILE), d));
+        }
+
+        for m in &self.mounts {
+            issues.append(&mut m.store.fsck());
+        }
+
+        issues
+    }
+
 
     /// Internal to get the default location of a store
     fn get_default_location() -> PathBuf {
@@ -270,6 +818,74 @@ impl PassStore {
             .collect()
     }
 
+    /// Like `find`, but only returns entries which additionally match all
+    /// `filters`. Therefore all entries matching `query` are decrypted, unless
+    /// they are up to date in the search index and the index covers all
+    /// fields used by `filters`.
+    pub fn find_with(&self, query: &str, filters: &[Filter]) -> Vec<PassTreePath> {
+        let candidates = self.find(query);
+        if filters.is_empty() {
+            return candidates;
+        }
+
+        let index = self.index_for(filters);
+        let mount_indexes: Vec<Option<Index>> = self.mounts
+            .iter()
+            .map(|m| m.store.index_for(filters))
+            .collect();
+
+        candidates
+            .into_iter()
+            .filter(|x| x.is_leaf())
+            .filter(|x| {
+                let name = x.to_string();
+                let (store, index, name) = match self.mount_for(&name) {
+                    Some((i, sub)) =>
+                        (&self.mounts[i].store, mount_indexes[i].as_ref(), sub),
+                    None => (self, index.as_ref(), name),
+                };
+
+                if let Some(index) = index {
+                    if index.is_current(&name, &store.entry_file(&name)) {
+                        let fields = &index.get(&name).unwrap().fields;
+                        return filters.iter().all(|f| f.matches(fields));
+                    }
+                }
+
+                match self.read(x) {
+                    Some(content) => {
+                        let fields = filter::parse_fields(&content);
+                        filters.iter().all(|f| f.matches(&fields))
+                    },
+                    None => {
+                        println_stderr!("Unable to read {}, skipped.", x);
+                        false
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the search index if it can answer `filters`.
+    fn index_for(&self, filters: &[Filter]) -> Option<Index> {
+        let covered = filters.iter().all(|f| match *f {
+            Filter::Field(ref k, _) => self.index_fields.contains(k),
+            Filter::Tag(_) => self.index_fields.iter()
+                .any(|k| k == filter::TAGS_FIELD),
+        });
+        if !covered {
+            return None;
+        }
+
+        match self.load_index() {
+            Ok(index) => index,
+            Err(err) => {
+                println_stderr!("Search index not used: {}", err);
+                None
+            },
+        }
+    }
+
     /// Get a `PassTreePath` from the give parameter `pass`. Returns an
     pub fn get<S>(&self, pass: S) -> Option<PassTreePath> where S: Into<String>
     {
@@ -279,20 +895,60 @@ impl PassStore {
             return Some(PassTreePath::from(vec![]));
         }
 
-        self.entries
-            .into_iter()
-            .find(|x| x.to_string() == pass)
+        if !self.loaded {
+            return self.get_from_fs(&pass);
+        }
+        if let Some(ref lookup) = self.lookup {
+            return lookup.get(&pass).map(|&leaf| path_from_str(&pass, leaf));
+        }
+
+        let names: Vec<&str> = pass.split('/').collect();
+        self.entries.lookup(&names, |e, n| e.display_name() == *n)
+    }
+
+    /// Looks up `pass` directly in the file-system, used while the store is
+    /// not loaded.
+    fn get_from_fs(&self, pass: &str) -> Option<PassTreePath> {
+        let hidden = [".", "..", ".git", template::TEMPLATES_DIR];
+        if pass.split('/').any(|c| c.is_empty() || hidden.contains(&c)) {
+            return None;
+        }
+
+        let path = self.absolute_path(pass);
+        let file = PathBuf::from(format!("{}.{}", path.to_str()?,
+                                         PASS_ENTRY_EXTENSION));
+        if file.is_file() {
+            Some(path_from_str(pass, true))
+        } else if path.is_dir() {
+            Some(path_from_str(pass, false))
+        } else {
+            None
+        }
     }
 
     /// Reads and returns the content of the given `PassEntry`. The for the
     /// gpg-file related to the `PassEntry` encrypt.
-    pub fn read(&self, entry: &PassTreePath) -> Option<String> {
-        let p = String::from(format!("{}.{}", entry.to_string(),
-                                    PASS_ENTRY_EXTENSION));
-        let p = self.passhome.clone().join(PathBuf::from(p));
+    pub fn read(&self, entry: &PassTreePath) -> Option<Secret> {
+        if let Some((i, sub)) = self.mount_for(&entry.to_string()) {
+            let m = &self.mounts[i];
+            return m.store.get(sub).and_then(|e| m.store.read(&e));
+        }
+
+        let p = self.entry_file(&entry.to_string());
         if self.verbose {
             println!("Read path: {}", p.to_str().unwrap());
         }
+        self.decrypt_file(&p)
+    }
+
+    /// Returns the location of the gpg-file of `entry`.
+    fn entry_file(&self, entry: &str) -> PathBuf {
+        let p = format!("{}.{}", entry, PASS_ENTRY_EXTENSION);
+        self.passhome.join(PathBuf::from(p))
+    }
+
+    /// Decrypts the gpg-file at `p`, errors are printed.
+    fn decrypt_file(&self, p: &PathBuf) -> Option<Secret> {
         let mut input = match gpgme::Data::load(p.to_str().unwrap()) {
             Ok(input) => input,
             Err(x) => {
@@ -301,77 +957,672 @@ impl PassStore {
             }
         };
 
-        let mut ctx = gpgme::Context::from_protocol(
-            gpgme::Protocol::OpenPgp).unwrap();
-        let mut output = gpgme::Data::new().unwrap();
-        match ctx.decrypt(&mut input, &mut output) {
-            Ok(..) => (),
+        match decrypt(&mut input) {
+            Ok(result) => Some(result),
             Err(x) => {
                 println_stderr!("Unable to decrypt {:?}: {}", p, x);
-                return None;
+                None
             }
         }
+    }
 
-        let mut result = String::new();
-        let _ = output.seek(io::SeekFrom::Start(0));
-        let _ = output.read_to_string(&mut result);
+    /// Returns the commits which changed `entry`, newest first, also if the
+    /// entry does not exist anymore.
+    pub fn history(&self, vcs: &Box<vcs::VersionControl>, entry: &str,
+                   max: usize) -> Result<Vec<vcs::Commit>>
+    {
+        if let Some((i, sub)) = self.mount_for(entry) {
+            let m = &self.mounts[i];
+            return m.store.history(&m.vcs, &sub, max);
+        }
+        let file = format!("{}.{}", entry, PASS_ENTRY_EXTENSION);
+        Ok(vcs.log(Some(&file), max)?)
+    }
+
+    /// Reads and decrypts `entry` as of revision `rev` of the version control.
+    pub fn read_rev(&self, vcs: &Box<vcs::VersionControl>, entry: &str,
+                    rev: &str) -> Result<Secret>
+    {
+        if let Some((i, sub)) = self.mount_for(entry) {
+            let m = &self.mounts[i];
+            return m.store.read_rev(&m.vcs, &sub, rev);
+        }
+        let file = format!("{}.{}", entry, PASS_ENTRY_EXTENSION);
+        let encrypted = vcs.file_at(rev, &file)?;
+        let mut input = gpgme::Data::from_buffer(&encrypted)?;
+        decrypt(&mut input)
+    }
 
-        Some(result)
+    /// Restores `entry` as of revision `rev`, by encrypting the old content
+    /// for the current `.gpg-id` and committing it.
+    pub fn restore(&mut self, vcs: &Box<vcs::VersionControl>, entry: &str,
+                   rev: &str) -> Result<()>
+    {
+        let content = self.read_rev(vcs, entry, rev)?;
+        self.write_entry(vcs, "restore", entry, &content,
+                         &|e| format!("Restore password {} from {}.", e, rev))
     }
 
     /// Inserts a new entry into the store. This creates a new encrypted
     /// gpg-file and add it to version control system, provided via `vcs`.
-    pub fn insert<D>(&mut self, vcs: &Box<vcs::VersionControl>, entry: &str, data: D) -> Result<()>
-            where D: Into<Vec<u8>>
+    /// Entries below a mounted store are inserted into that store and
+    /// committed with its own version control.
+    pub fn insert(&mut self, vcs: &Box<vcs::VersionControl>, entry: &str,
+                  data: &Secret) -> Result<()>
     {
+        self.write_entry(vcs, "add", entry, data,
+                         &|e| format!("Add given password {} to store.", e))
+    }
+
+    /// Replaces the content of `entry` with `data`, the entry is created if
+    /// it does not exist yet. Same as `insert` apart from the commit message.
+    pub fn edit(&mut self, vcs: &Box<vcs::VersionControl>, entry: &str,
+                data: &Secret) -> Result<()>
+    {
+        self.write_entry(vcs, "edit", entry, data,
+                         &|e| format!("Edit password {} in store.", e))
+    }
+
+    /// Encrypts `data` to the gpg-file of `entry` and commits it as
+    /// `operation` with the message returned by `message` for the entry name.
+    fn write_entry(&mut self, vcs: &Box<vcs::VersionControl>, operation: &str,
+                   entry: &str, data: &Secret, message: &Fn(&str) -> String)
+        -> Result<()>
+    {
+        if let Some((i, sub)) = self.mount_for(entry) {
+            if sub.is_empty() {
+                let s = format!("{} is a mounted store.", entry);
+                return Err(PassStoreError::Other(s));
+            }
+            let m = &mut self.mounts[i];
+            return m.store.write_entry(&m.vcs, operation, &sub, data,
+                                         message);
+        }
+
+        let _lock = try!(self.lock());
+
         let mut path = self.passhome.clone().join(entry);
         path.set_extension(PASS_ENTRY_EXTENSION);
 
-        let mut ctx = gpgme::Context::from_protocol(
-            gpgme::Protocol::OpenPgp).unwrap();
-        let key = try!(ctx.find_key(&*self.gpgid));
-        let mut input = try!(gpgme::Data::from_bytes(data.into()));
-        let mut output = try!(gpgme::Data::new());
-
-        let flags = gpgme::ENCRYPT_NO_ENCRYPT_TO | gpgme::ENCRYPT_NO_COMPRESS;
-        try!(ctx.encrypt_with_flags(Some(&key), &mut input, &mut output, flags));
+        let gpgid = try!(self.gpgid_for(entry));
 
-        try!(output.seek(io::SeekFrom::Start(0)));
         if self.verbose {
             println!("Going to write file: {}", path.to_str().unwrap_or(""));
         }
-        let mut outfile = try!(File::create(&path));
-        try!(io::copy(&mut output, &mut outfile));
-
+        self.encrypt_file(&gpgid, data, &path)?;
         try!(vcs.add(path.to_str().unwrap()));
-        try!(vcs.commit(&format!("Add given password {} to store.", entry)));
 
-        Ok(())
+        let indexable = self.root_gpgid().ok() == Some(gpgid);
+        self.update_index(vcs, |index| {
+            if indexable {
+                index.update(entry, &path, data);
+            } else {
+                index.remove(entry);
+            }
+        });
+
+        self.commit(vcs, operation, entry, &message(entry))
     }
 
-    /// Removes a given `PassEntry` from the store. Therefore the related
-    /// gpg-file will be removed from the file-system and the internal entry
-    /// list. Further the `vcs` will use to commit that change.
-    ///
-    /// Note that the `entry` passed into the function shall be a copy of the
-    /// original reference.
-    pub fn remove(&mut self, vcs: &Box<vcs::VersionControl>,
-                       entry: &PassTreePath) -> Result<()>
+    /// Commits the staged changes of `operation` on `entry` with `message`,
+    /// formatted by the message template. Within a transaction the formatted
+    /// message is only recorded.
+    fn commit(&self, vcs: &Box<vcs::VersionControl>, operation: &str,
+              entry: &str, message: &str) -> Result<()>
     {
-        if self.verbose {
-            println!("Remove {}", entry);
+        let message = format_message(&self.message_template, operation, entry,
+                                     message);
+        if let Some(ref mut tx) = *self.transaction.borrow_mut() {
+            tx.messages.push(message);
+            return Ok(());
+        }
+        vcs.commit(&message)?;
+        Ok(())
+    }
+
+    /// Saves the current content of `path` within a transaction, so that it
+    /// can be restored on rollback.
+    fn backup(&self, path: &Path) -> Result<()> {
+        if let Some(ref mut tx) = *self.transaction.borrow_mut() {
+            if tx.backups.iter().any(|(p, _)| p == path) {
+                return Ok(());
+            }
+            let content = match fs::read(path) {
+                Ok(content) => Some(content),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(PassStoreError::Io(e)),
+            };
+            tx.backups.push((path.to_path_buf(), content));
+        }
+        Ok(())
+    }
+
+    /// Runs `f` as a transaction of this store and all mounted stores. The
+    /// changes made through the store passed to `f` are staged, but not
+    /// committed until `f` succeeded. Then every store with changes makes a
+    /// single commit with the summary `message`, which lists the messages
+    /// of the single changes. If `f` or a commit fails, the changed files are
+    /// restored and unstaged. Each store is locked from its first change
+    /// until the end of the transaction.
+    ///
+    /// Note that mounted stores commit before the store itself, hence a
+    /// failed commit of the store does not roll back the commits of mounted
+    /// stores.
+    pub fn transaction<F, T>(&mut self, vcs: &Box<vcs::VersionControl>,
+                             message: &str, f: F) -> Result<T>
+        where F: FnOnce(&mut PassStore, &Box<vcs::VersionControl>) -> Result<T>
+    {
+        if self.transaction.borrow().is_some() {
+            let s = String::from("A transaction is already running.");
+            return Err(PassStoreError::Other(s));
+        }
+
+        self.begin_transaction();
+        let result = f(self, vcs)
+            .and_then(|r| self.commit_transaction(vcs, message).map(|_| r));
+        if result.is_err() {
+            self.rollback_transaction(vcs);
+        }
+        self.end_transaction();
+        result
+    }
+
+    fn begin_transaction(&self) {
+        *self.transaction.borrow_mut() = Some(Transaction::default());
+        for m in &self.mounts {
+            m.store.begin_transaction();
+        }
+    }
+
+    fn end_transaction(&self) {
+        *self.transaction.borrow_mut() = None;
+        for m in &self.mounts {
+            m.store.end_transaction();
+        }
+    }
+
+    /// Commits the changes of the transaction, committed changes are not
+    /// rolled back anymore.
+    fn commit_transaction(&self, vcs: &Box<vcs::VersionControl>, message: &str)
+        -> Result<()>
+    {
+        for m in &self.mounts {
+            m.store.commit_transaction(&m.vcs, message)?;
+        }
+
+        let messages = match *self.transaction.borrow_mut() {
+            Some(ref mut tx) => ::std::mem::take(&mut tx.messages),
+            None => return Ok(()),
+        };
+        if !messages.is_empty() {
+            let summary = format_message(&self.message_template,
+                                         "transaction", "", message);
+            vcs.commit(&format!("{}\n\n{}", summary, messages.join("\n")))?;
+        }
+        if let Some(ref mut tx) = *self.transaction.borrow_mut() {
+            tx.backups.clear();
+        }
+        Ok(())
+    }
+
+    /// Restores the files changed by the transaction and unstages them.
+    /// Failures are only reported, to restore as much as possible.
+    fn rollback_transaction(&mut self, vcs: &Box<vcs::VersionControl>) {
+        for m in &mut self.mounts {
+            m.store.rollback_transaction(&m.vcs);
+        }
+
+        let backups = match *self.transaction.borrow_mut() {
+            Some(ref mut tx) => ::std::mem::take(&mut tx.backups),
+            None => return,
+        };
+        for (path, content) in backups.into_iter().rev() {
+            if self.verbose {
+                println!("Roll back {:?}", path);
+            }
+            let file = path.to_str().unwrap_or("");
+            let result = match content {
+                Some(content) => self.ops.write(&path, &mut content.as_slice())
+                    .map_err(PassStoreError::from)
+                    .and_then(|_| Ok(vcs.add(file)?)),
+                None => {
+                    if path.is_file() {
+                        let _ = self.ops.remove_file(&path);
+                    }
+                    // fails if the file was not staged yet
+                    let _ = vcs.remove(file);
+                    Ok(())
+                },
+            };
+            if let Err(err) = result {
+                println_stderr!("Unable to roll back {:?}: {}", path, err);
+            }
+        }
+
+        if let Err(err) = self.reload() {
+            println_stderr!("Unable to reload the store: {}", err);
+        }
+    }
+
+    /// Encrypts `data` for `gpgid` and writes it to `path`.
+    fn encrypt_file(&self, gpgid: &str, data: &Secret, path: &Path)
+        -> Result<()>
+    {
+        let mut ctx = gpgme::Context::from_protocol(
+            gpgme::Protocol::OpenPgp).unwrap();
+        let key = try!(ctx.find_key(gpgid));
+        let mut input = try!(gpgme::Data::from_buffer(data.as_bytes()));
+        let mut output = try!(gpgme::Data::new());
+
+        let flags = gpgme::ENCRYPT_NO_ENCRYPT_TO | gpgme::ENCRYPT_NO_COMPRESS;
+        try!(ctx.encrypt_with_flags(Some(&key), &mut input, &mut output, flags));
+
+        try!(output.seek(io::SeekFrom::Start(0)));
+        self.backup(path)?;
+        self.ops.write(path, &mut output)?;
+
+        Ok(())
+    }
+
+    /// Returns the gpg-id of the store root, which is used for the index.
+    fn root_gpgid(&self) -> Result<String> {
+        self.gpgid_for(index::INDEX_FILE)
+    }
+
+    /// Loads the search index of the store. Returns `None` if the index is
+    /// disabled, does not exist or was built for other fields.
+    pub fn load_index(&self) -> Result<Option<Index>> {
+        let path = self.passhome.join(index::INDEX_FILE);
+        if self.index_fields.is_empty() || !path.is_file() {
+            return Ok(None);
+        }
+
+        let content = match self.decrypt_file(&path) {
+            Some(content) => content,
+            None => {
+                let s = format!("Unable to decrypt index {:?}", path);
+                return Err(PassStoreError::Other(s));
+            }
+        };
+        let index = Index::parse(&content).map_err(|e| {
+            PassStoreError::Other(format!("Invalid index {:?}: {}", path, e))
+        })?;
+
+        if index.fields() != &self.index_fields[..] {
+            return Ok(None);
+        }
+        Ok(Some(index))
+    }
+
+    /// Encrypts `index` to the gpg-id of the store root and adds it to `vcs`.
+    fn save_index(&self, vcs: &Box<vcs::VersionControl>, index: &Index)
+        -> Result<()>
+    {
+        let path = self.passhome.join(index::INDEX_FILE);
+        let gpgid = self.root_gpgid()?;
+        self.encrypt_file(&gpgid, &index.to_secret(), &path)?;
+        vcs.add(path.to_str().unwrap())?;
+        Ok(())
+    }
+
+    /// Applies `f` to an existing index and saves it. Failures are only
+    /// reported, since the change of the store itself succeeded; `fsck`
+    /// detects the outdated index.
+    fn update_index<F>(&self, vcs: &Box<vcs::VersionControl>, f: F)
+        where F: FnOnce(&mut Index)
+    {
+        let result = self.load_index().and_then(|index| match index {
+            Some(mut index) => {
+                f(&mut index);
+                self.save_index(vcs, &index)
+            },
+            None => Ok(()),
+        });
+        if let Err(err) = result {
+            println_stderr!("Unable to update the search index: {}", err);
+        }
+    }
+
+    /// Returns the names and gpg-files of all entries of this store (not of
+    /// the mounted ones) which are encrypted to the gpg-id of the store root,
+    /// i.e. which may be indexed.
+    fn indexable_entries(&self) -> Result<Vec<(String, PathBuf)>> {
+        let root = self.root_gpgid()?;
+        // the .gpg-id lookup verifies signatures, do it once per folder
+        let mut gpgids: HashMap<PathBuf, Option<String>> = HashMap::new();
+
+        let mut result = vec![];
+        for e in &self.entries {
+            let name = e.to_string();
+            if !e.is_leaf() || self.mount_for(&name).is_some() {
+                continue;
+            }
+            let path = self.entry_file(&name);
+            if !path.is_file() {
+                continue;
+            }
+            let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+            let gpgid = gpgids.entry(dir)
+                .or_insert_with(|| self.gpgid_for(&name).ok());
+            if gpgid.as_ref() == Some(&root) {
+                result.push((name, path));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Rebuilds the search index from scratch, by decrypting all entries. The
+    /// indexes of mounted stores are rebuilt as well. Returns the number of
+    /// indexed entries.
+    pub fn rebuild_index(&self, vcs: &Box<vcs::VersionControl>) -> Result<usize> {
+        let mut count = 0;
+        for m in &self.mounts {
+            count += m.store.rebuild_index(&m.vcs)?;
+        }
+        if self.index_fields.is_empty() {
+            return Ok(count);
+        }
+
+        let _lock = self.lock()?;
+
+        let mut index = Index::new(&self.index_fields);
+        for (name, path) in self.indexable_entries()? {
+            match self.decrypt_file(&path) {
+                Some(content) => index.update(&name, &path, &content),
+                None => println_stderr!("Unable to read {}, not indexed.", name),
+            }
+        }
+        count += index.entries().len();
+
+        self.save_index(vcs, &index)?;
+        self.commit(vcs, "index", "", "Rebuild search index.")?;
+
+        Ok(count)
+    }
+
+    /// Returns a description of each difference between the search index and
+    /// the store, e.g. entries changed by other tools.
+    fn index_drift(&self) -> Vec<String> {
+        if self.index_fields.is_empty() {
+            return vec![];
+        }
+        let index = match self.load_index() {
+            Ok(Some(index)) => index,
+            Ok(None) => return vec![String::from("index is missing or outdated")],
+            Err(err) => return vec![err.to_string()],
+        };
+        let entries = match self.indexable_entries() {
+            Ok(entries) => entries,
+            Err(err) => return vec![err.to_string()],
+        };
+
+        let mut drift = vec![];
+        for (name, path) in &entries {
+            if index.get(name).is_none() {
+                drift.push(format!("{} is not indexed", name));
+            } else if !index.is_current(name, path) {
+                drift.push(format!("{} changed since it was indexed", name));
+            }
+        }
+        for name in index.entries() {
+            if !entries.iter().any(|(n, _)| n == name) {
+                drift.push(format!("{} is indexed but not in the store", name));
+            }
+        }
+        drift
+    }
+
+    /// Removes a given `PassEntry` from the store. Therefore the related
+    /// gpg-file will be removed from the file-system and the internal entry
+    /// list. Further the `vcs` will use to commit that change.
+    ///
+    /// Note that the `entry` passed into the function shall be a copy of the
+    /// original reference.
+    pub fn remove(&mut self, vcs: &Box<vcs::VersionControl>,
+                       entry: &PassTreePath) -> Result<()>
+    {
+        if self.verbose {
+            println!("Remove {}", entry);
+        }
+
+        self.remove_from_tree(entry);
+
+        if let Some((i, sub)) = self.mount_for(&entry.to_string()) {
+            let m = &mut self.mounts[i];
+            return match m.store.get(sub) {
+                Some(e) => m.store.remove(&m.vcs, &e),
+                None => {
+                    let s = format!("{} is not in the password store.", entry);
+                    Err(PassStoreError::Other(s))
+                }
+            };
+        }
+
+        let _lock = try!(self.lock());
+
+        let mut p = self.absolute_path(&entry.to_string());
+        p.set_extension(PASS_ENTRY_EXTENSION);
+        println!("{:?}", p);
+        try!(self.backup(&p));
+        try!(self.ops.remove_file(&p));
+
+        try!(vcs.remove(p.to_str().unwrap()));
+        let name = entry.to_string();
+        self.update_index(vcs, |index| { index.remove(&name); });
+        let message = format!("Remove {} from store.", name);
+        self.commit(vcs, "remove", &name, &message)
+    }
+
+    /// Removes `entry` and everything below it from `entries` and the lookup
+    /// index.
+    fn remove_from_tree(&mut self, entry: &PassTreePath) {
+        self.entries.remove(entry);
+        if let Some(ref mut lookup) = self.lookup {
+            let name = entry.to_string();
+            let prefix = format!("{}/", name);
+            lookup.retain(|k, _| *k != name && !k.starts_with(&prefix));
+        }
+    }
+
+    /// Reverts the most recent commit made by rass, by a new commit. Fails if
+    /// there are newer commits not made by rass, unless `force` is set, or if
+    /// the store has uncommitted changes. The store is reloaded afterwards.
+    /// Returns the subject of the reverted commit.
+    pub fn undo(&mut self, vcs: &Box<vcs::VersionControl>, force: bool)
+        -> Result<String>
+    {
+        if self.transaction.borrow().is_some() {
+            let s = String::from("Undo is not possible within a transaction.");
+            return Err(PassStoreError::Other(s));
+        }
+        let _lock = self.lock()?;
+        self.check_committed(vcs, "Undo")?;
+
+        let commits = vcs.log(None, UNDO_SEARCH_DEPTH)?;
+        let pos = match commits.iter().position(|c| vcs::is_rass_commit(&c.message)) {
+            Some(pos) => pos,
+            None => {
+                let s = format!("No commit made by rass found in the last {} \
+                                 commits.", UNDO_SEARCH_DEPTH);
+                return Err(PassStoreError::Other(s));
+            }
+        };
+        if pos > 0 && !force {
+            let s = format!("{} newer commit(s) not made by rass, the latest is \
+                             '{}'. Use --force to undo anyway.",
+                            pos, commits[0].subject());
+            return Err(PassStoreError::Other(s));
+        }
+
+        let commit = &commits[pos];
+        if self.verbose {
+            println!("Revert {} {}", commit.id, commit.subject());
+        }
+        if let Err(e) = vcs.revert(&commit.id) {
+            let s = format!("Unable to revert {}: {}. Resolve the conflicts and \
+                             commit with 'rass git commit'.", commit.id, e);
+            return Err(PassStoreError::Other(s));
+        }
+        self.commit(vcs, "undo", "", &format!("Undo \"{}\"", commit.subject()))?;
+
+        self.reload()?;
+        Ok(commit.subject().to_string())
+    }
+
+    /// Fails if the store has changes which are not committed, since
+    /// `operation` would mix them with its own changes or stop on them.
+    fn check_committed(&self, vcs: &Box<vcs::VersionControl>,
+                       operation: &str) -> Result<()>
+    {
+        let changes = match vcs.status() {
+            Ok(changes) => changes,
+            // the operation itself reports that it is not supported
+            Err(vcs::VcsError::Unsupported(..)) => return Ok(()),
+            Err(err) => return Err(PassStoreError::from(err)),
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let s = format!("{} is not possible with uncommitted changes of {}, \
+                         commit or remove them first.", operation,
+                        changes.join(", "));
+        Err(PassStoreError::Other(s))
+    }
+
+    /// Synchronizes the store and all mounted stores with their remotes: the
+    /// remote changes are fetched, the local commits are rebased onto them
+    /// and pushed. Conflicting entries are decrypted and passed to `resolve`.
+    /// Other conflicting files, apart from the search index, abort the sync.
+    /// Mounted stores without version control are skipped. Returns the
+    /// number of resolved conflicts.
+    pub fn sync<F>(&mut self, vcs: &Box<vcs::VersionControl>, mut resolve: F)
+        -> Result<usize>
+        where F: FnMut(&EntryConflict) -> Resolution
+    {
+        if self.transaction.borrow().is_some() {
+            let s = String::from("Sync is not possible within a transaction.");
+            return Err(PassStoreError::Other(s));
+        }
+        self.sync_with(vcs, "", &mut resolve)
+    }
+
+    fn sync_with(&mut self, vcs: &Box<vcs::VersionControl>, prefix: &str,
+                 resolve: &mut FnMut(&EntryConflict) -> Resolution)
+        -> Result<usize>
+    {
+        let mut count = 0;
+        {
+            let _lock = self.lock()?;
+            self.check_committed(vcs, "Sync")?;
+            let mut conflicts = vcs.pull()?;
+            while !conflicts.is_empty() {
+                for c in &conflicts {
+                    if self.verbose {
+                        println!("Resolve conflict of {}", c.path);
+                    }
+                    if let Err(err) = self.resolve_conflict(vcs, prefix, c, resolve) {
+                        if let Err(e) = vcs.abort_pull() {
+                            println_stderr!("Unable to abort the sync: {}", e);
+                        }
+                        return Err(err);
+                    }
+                    count += 1;
+                }
+                conflicts = match vcs.continue_pull() {
+                    Ok(conflicts) => conflicts,
+                    Err(err) => {
+                        if let Err(e) = vcs.abort_pull() {
+                            println_stderr!("Unable to abort the sync: {}", e);
+                        }
+                        return Err(PassStoreError::from(err));
+                    }
+                };
+            }
+            vcs.push()?;
+        }
+
+        for m in &mut self.mounts {
+            let prefix = join_entry(prefix, &m.prefix);
+            match m.store.sync_with(&m.vcs, &prefix, resolve) {
+                Ok(n) => count += n,
+                Err(PassStoreError::Vcs(vcs::VcsError::Unsupported(..))) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.reload()?;
+        Ok(count)
+    }
+
+    /// Resolves the conflict `c` of a sync, the result is staged.
+    fn resolve_conflict(&self, vcs: &Box<vcs::VersionControl>, prefix: &str,
+                        c: &vcs::MergeConflict,
+                        resolve: &mut FnMut(&EntryConflict) -> Resolution)
+        -> Result<()>
+    {
+        let path = self.passhome.join(&c.path);
+        if c.path == index::INDEX_FILE {
+            println_stderr!("The search index conflicts, the remote one is kept. \
+                             Run 'rass index rebuild' after the sync.");
+            return self.keep_version(vcs, &path, c.remote.as_ref());
+        }
+
+        let suffix = format!(".{}", PASS_ENTRY_EXTENSION);
+        let entry = match c.path.strip_suffix(&suffix) {
+            Some(entry) => entry,
+            None => {
+                let s = format!("{} conflicts, which is not an entry. Resolve \
+                                 it with 'rass git pull --rebase'.", c.path);
+                return Err(PassStoreError::Other(s));
+            }
+        };
+
+        let decrypt_version = |data: &Option<Vec<u8>>| -> Result<Option<Secret>> {
+            match *data {
+                Some(ref data) => {
+                    let mut input = gpgme::Data::from_buffer(data)?;
+                    Ok(Some(decrypt(&mut input)?))
+                },
+                None => Ok(None),
+            }
+        };
+        let conflict = EntryConflict {
+            entry: join_entry(prefix, entry),
+            base: decrypt_version(&c.base)?,
+            local: decrypt_version(&c.local)?,
+            remote: decrypt_version(&c.remote)?,
+        };
+
+        match resolve(&conflict) {
+            Resolution::Merged(content) => {
+                let gpgid = self.gpgid_for(entry)?;
+                self.encrypt_file(&gpgid, &content, &path)?;
+                vcs.add(path.to_str().unwrap())?;
+                Ok(())
+            },
+            Resolution::Local => self.keep_version(vcs, &path, c.local.as_ref()),
+            Resolution::Remote => self.keep_version(vcs, &path, c.remote.as_ref()),
+            Resolution::Abort => Err(PassStoreError::Other(String::from("Sync aborted."))),
+        }
+    }
+
+    /// Stages the given version of the file at `path`, `None` removes it.
+    fn keep_version(&self, vcs: &Box<vcs::VersionControl>, path: &Path,
+                    content: Option<&Vec<u8>>) -> Result<()>
+    {
+        let file = path.to_str().unwrap();
+        match content {
+            Some(content) => {
+                self.ops.write(path, &mut content.as_slice())?;
+                vcs.add(file)?;
+            },
+            None => {
+                if path.is_file() {
+                    self.ops.remove_file(path)?;
+                }
+                vcs.remove(file)?;
+            },
         }
-
-        self.entries.remove(entry);
-
-        let mut p = self.absolute_path(&entry.to_string());
-        p.set_extension(PASS_ENTRY_EXTENSION);
-        println!("{:?}", p);
-        try!(fs::remove_file(&p));
-
-        try!(vcs.remove(p.to_str().unwrap()));
-        try!(vcs.commit(&format!("Remove {} from store.", entry.to_string())));
-
         Ok(())
     }
 
@@ -393,6 +1644,12 @@ impl PassStore {
     }
 
 
+    /// Prints the tree of the store restricted to the given `paths`.
+    pub fn print_paths(&self, paths: &[PassTreePath]) {
+        let printer = tree::TreePrinter::new();
+        printer.print(&self.entries.pruned(paths));
+    }
+
     /// Executes over all entries in the store with the given search parameters.
     /// Take note that `grep_args` can include all grep parameters which are
     /// relevant for a piped grep execution. However, the last parameter shall
@@ -419,8 +1676,9 @@ impl PassStore {
             }
             let content = content.unwrap();
 
+            let color = if self.color { "--color=always" } else { "--color=never" };
             let grep = match Command::new(searcher)
-                .arg("--color=always")
+                .arg(color)
                 .args(grep_args.as_slice())
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
@@ -492,25 +1750,118 @@ impl PassEntry {
             name: name,
         }
     }
-}
 
-impl fmt::Display for PassEntry {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    /// Returns the name of the entry without the extension of the gpg-file.
+    pub fn display_name(&self) -> &str {
         if self.name.ends_with(".gpg") {
-            write!(f, "{}", &self.name[..self.name.len()-4])
+            &self.name[..self.name.len()-4]
         }
         else {
-            write!(f, "{}", &self.name)
+            &self.name
         }
     }
 }
 
+impl fmt::Display for PassEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display_name())
+    }
+}
+
 impl convert::Into<String> for PassEntry {
     fn into(self) -> String {
         self.name
     }
 }
 
+/// Represents a problem found by `PassStore::fsck`.
+#[derive(Debug)]
+pub enum FsckIssue {
+    UnsignedGpgId(PathBuf),
+    InvalidGpgIdSignature(PathBuf, String),
+    IndexDrift(PathBuf, String),
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FsckIssue::UnsignedGpgId(ref p) =>
+                write!(f, "{:?} is not signed", p),
+            FsckIssue::InvalidGpgIdSignature(ref p, ref err) =>
+                write!(f, "{:?} has no valid signature: {}", p, err),
+            FsckIssue::IndexDrift(ref p, ref drift) =>
+                write!(f, "{:?} is out of date: {}", p, drift),
+        }
+    }
+}
+
+/// Returns the name of `entry` of a store mounted at `prefix`.
+fn join_entry(prefix: &str, entry: &str) -> String {
+    if prefix.is_empty() {
+        entry.to_string()
+    } else {
+        format!("{}/{}", prefix, entry)
+    }
+}
+
+/// Formats the commit message of `operation` on `entry` by the message
+/// `template`, see `PassStore::set_message_template`. Unknown placeholders
+/// are kept.
+fn format_message(template: &str, operation: &str, entry: &str,
+                  message: &str) -> String {
+    let mut result = String::with_capacity(template.len() + message.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        // up to the closing brace, unless another placeholder starts first
+        let end = match rest[1..].find(['{', '}']) {
+            Some(i) if rest[i + 1..].starts_with('}') => i + 2,
+            _ => 1,
+        };
+        match &rest[..end] {
+            "{operation}" => result.push_str(operation),
+            "{entry}" => result.push_str(entry),
+            "{hostname}" => result.push_str(&::util::hostname()),
+            "{message}" => result.push_str(message),
+            other => result.push_str(other),
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Decrypts `input`. The result is read into a buffer of the exact size, so
+/// that it is not reallocated and no copies of the decrypted content are left
+/// behind.
+fn decrypt(input: &mut gpgme::Data) -> Result<Secret> {
+    let mut ctx = gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp)?;
+    let mut output = gpgme::Data::new()?;
+    ctx.decrypt(input, &mut output)?;
+
+    let len = output.seek(io::SeekFrom::End(0))? as usize;
+    let mut result = Secret::new(vec![0; len]);
+    output.seek(io::SeekFrom::Start(0))?;
+    output.read_exact(result.as_mut_bytes())?;
+
+    Ok(result)
+}
+
+/// Returns the `PassTreePath` for the entry name `pass`, e.g. `team/db`.
+fn path_from_str(pass: &str, is_leaf: bool) -> PassTreePath {
+    let mut elements: Vec<PassEntry> = pass.split('/')
+        .map(|x| PassEntry { name: x.to_string() })
+        .collect();
+    // the elements of the tree are named like the files
+    if is_leaf {
+        if let Some(last) = elements.last_mut() {
+            last.name = format!("{}.{}", last.name, PASS_ENTRY_EXTENSION);
+        }
+    }
+    PassTreePath::new(elements, is_leaf)
+}
+
 fn get_gpgid_from_file(path: &PathBuf) -> Result<String> {
     let f = try!(fs::File::open(path));
     let mut  reader = io::BufReader::new(f);
@@ -520,10 +1871,35 @@ fn get_gpgid_from_file(path: &PathBuf) -> Result<String> {
     Ok(buffer.trim().to_string())
 }
 
-fn write_gpgid_to_file(path: &PathBuf, gpgid: &String) -> Result<()> {
-    let mut file = File::create(path)?;
-    file.write_all(&gpgid.clone().into_bytes())?;
-    file.write_all(b"\n")?;
+fn find_gpgid_files(path: &PathBuf) -> Vec<PathBuf> {
+    let mut result = vec![];
+
+    let rd = match fs::read_dir(path) {
+        Ok(r) => r,
+        Err(_) => return result,
+    };
+    for entry in rd {
+        let p = match entry {
+            Ok(e) => e.path(),
+            Err(_) => continue
+        };
+        if p.ends_with(".git") {
+            continue;
+        }
+        if p.is_dir() {
+            result.append(&mut find_gpgid_files(&p));
+        } else if p.file_name() == Some(ffi::OsStr::new(PASS_GPGID_FILE)) {
+            result.push(p);
+        }
+    }
+    result
+}
+
+fn write_gpgid_to_file(ops: &Operations, path: &PathBuf, gpgid: &String)
+    -> Result<()>
+{
+    let content = format!("{}\n", gpgid);
+    ops.write(path, &mut content.as_bytes())?;
     Ok(())
 }
 
@@ -548,4 +1924,657 @@ mod test {
             assert_eq!("foobar.com", &format!("{}",entry));
         }
     }
+
+    mod mount {
+        use std::fs;
+        use ::store::PassStore;
+        use ::testutil::temp_dir;
+        use ::vcs::NoVcs;
+
+        #[test]
+        fn test_mount() {
+            let base = temp_dir("mount");
+            let home = base.join("home");
+            let team = base.join("team");
+            fs::create_dir_all(home.join("web")).unwrap();
+            fs::create_dir_all(team.join("db")).unwrap();
+            fs::write(home.join("web/mail.gpg"), "").unwrap();
+            fs::write(team.join("db/prod.gpg"), "").unwrap();
+
+            let mut store = PassStore::from(&home).unwrap();
+            let sub = PassStore::from(&team).unwrap();
+            store.mount("shared/team", sub, Box::new(NoVcs{})).unwrap();
+
+            assert!(store.get("web/mail").is_some());
+            assert!(store.get("shared/team/db/prod").unwrap().is_leaf());
+            assert_eq!(store.find("prod").len(), 1);
+            assert_eq!(store.absolute_path("shared/team/db/prod"),
+                       team.join("db/prod"));
+            assert_eq!(store.mounts().len(), 1);
+
+            let sub = PassStore::from(&team).unwrap();
+            assert!(store.mount("shared/team/", sub, Box::new(NoVcs{})).is_err());
+
+            fs::remove_dir_all(&base).unwrap();
+        }
+    }
+
+    mod lookup {
+        use std::cell::Cell;
+        use std::fs;
+        use std::mem;
+        use std::path::PathBuf;
+        use ::config::Config;
+        use ::store::{PassEntry, PassStore, PassTree};
+        use ::testutil::temp_dir;
+
+        #[test]
+        fn test_lazy_get() {
+            let base = temp_dir("lazy");
+            let home = base.join("home");
+            let team = base.join("team");
+            fs::create_dir_all(home.join("web/.git")).unwrap();
+            fs::create_dir_all(team.join("db")).unwrap();
+            fs::write(home.join("web/mail.gpg"), "").unwrap();
+            fs::write(team.join("db/prod.gpg"), "").unwrap();
+
+            let config = Config {
+                store: home.clone(),
+                mounts: vec![(String::from("team"), team.clone())],
+                ..Config::default()
+            };
+            let mut store = PassStore::from_config_lazy(&config).unwrap();
+            assert!(!store.is_loaded());
+            assert!(store.get("web/mail").unwrap().is_leaf());
+            assert!(!store.get("web").unwrap().is_leaf());
+            assert!(store.get("team/db/prod").unwrap().is_leaf());
+            assert!(store.get("web/.git").is_none());
+            assert!(store.get("web/../web/mail").is_none());
+            assert!(store.get("web/foo").is_none());
+
+            store.load().unwrap();
+            assert_eq!(store.get("team/db/prod").unwrap().to_string(),
+                       "team/db/prod");
+            assert!(store.get("web/.git").is_none());
+            assert_eq!(store.find("").len(), 5);
+
+            fs::remove_dir_all(&base).unwrap();
+        }
+
+        /// Lookups in a synthetic store of 50k entries only visit the sub-trees
+        /// on the way, with the hash index not even those.
+        #[test]
+        fn test_get_50k() {
+            let entry = |name: String| PassEntry { name: name };
+            let leaf = |name: String| entry(format!("{}.gpg", name));
+            let mut root = PassTree::new(entry(String::from("Password Store")));
+            root.set_root(true);
+            for d in 0..100 {
+                let mut dir = PassTree::new(entry(format!("dir{}", d)));
+                for e in 0..500 {
+                    dir.add(PassTree::new(leaf(format!("entry{}", e))));
+                }
+                root.add(dir);
+            }
+
+            let mut store = PassStore::unloaded(&PathBuf::from("/nonexistent"));
+            store.entries = root;
+            store.loaded = true;
+
+            // the last entry is compared with all folders and the entries of
+            // its folder, but not with the other 49.5k entries
+            let visited = Cell::new(0);
+            let path = store.entries.lookup(&["dir99", "entry499"], |e, n| {
+                visited.set(visited.get() + 1);
+                e.display_name() == *n
+            });
+            assert!(path.unwrap().is_leaf());
+            assert_eq!(visited.get(), 100 + 500);
+            assert!(store.get("dir99/entry499").unwrap().is_leaf());
+            assert!(store.get("dir0/entry500").is_none());
+
+            // the hash index answers lookups without the tree
+            store.build_lookup_index().unwrap();
+            let entries = mem::replace(&mut store.entries,
+                                       PassTree::new(entry(String::new())));
+            assert!(store.get("dir99/entry499").unwrap().is_leaf());
+            assert!(!store.get("dir99").unwrap().is_leaf());
+            assert!(store.get("dir0/entry500").is_none());
+            store.entries = entries;
+
+            let p = store.get("dir0/entry1").unwrap();
+            store.remove_from_tree(&p);
+            assert!(store.get("dir0/entry1").is_none());
+        }
+    }
+
+    mod undo {
+        use std::fs;
+        use ::store::PassStore;
+        use ::testutil::{git, git_init, temp_dir};
+        use ::vcs;
+
+        #[test]
+        fn test_undo() {
+            let home = temp_dir("undo");
+            git_init(&home);
+
+            let vcs = vcs::from_path(home.to_str().unwrap()).unwrap();
+            fs::write(home.join("foo.gpg"), "").unwrap();
+            vcs.add("foo.gpg").unwrap();
+            vcs.commit("Add given password foo to store.").unwrap();
+
+            let mut store = PassStore::from(&home).unwrap();
+            assert!(store.get("foo").is_some());
+
+            // uncommitted changes would be part of the undo
+            fs::write(home.join("bar.gpg"), "").unwrap();
+            let err = store.undo(&vcs, false).unwrap_err();
+            assert!(err.to_string().contains("uncommitted changes of bar.gpg"));
+            fs::remove_file(home.join("bar.gpg")).unwrap();
+
+            assert_eq!(store.undo(&vcs, false).unwrap(),
+                       "Add given password foo to store.");
+            assert!(store.get("foo").is_none());
+
+            // undo of the undo restores the entry
+            store.undo(&vcs, false).unwrap();
+            assert!(store.get("foo").is_some());
+
+            fs::write(home.join("README"), "").unwrap();
+            git(&home, &["add", "README"]);
+            git(&home, &["commit", "-q", "-m", "Add README"]);
+            assert!(store.undo(&vcs, false).is_err());
+            store.undo(&vcs, true).unwrap();
+            assert!(store.get("foo").is_none());
+            assert!(home.join("README").is_file());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod history {
+        use std::fs;
+        use ::secret::Secret;
+        use ::store::PassStore;
+        use ::testutil::{git_init, gpg_key, temp_dir};
+        use ::vcs;
+
+        #[test]
+        fn test_history() {
+            let home = temp_dir("history");
+            git_init(&home);
+            let vcs = vcs::from_path(home.to_str().unwrap()).unwrap();
+            let commit = |name: &str, content: &[u8], message: &str| {
+                fs::write(home.join(name), content).unwrap();
+                vcs.add(name).unwrap();
+                vcs.commit(message).unwrap();
+            };
+            commit("mail.gpg", b"\x84\x0c\x03v1", "Add mail");
+            commit("shop.gpg", b"\x84\x0c\x03v1", "Add shop");
+            commit("mail.gpg", b"\x84\x0c\x03v2", "Edit mail");
+
+            let store = PassStore::from(&home).unwrap();
+            let history = store.history(&vcs, "mail", 10).unwrap();
+            let subjects: Vec<&str> = history.iter().map(|c| c.subject())
+                .collect();
+            assert_eq!(subjects, ["Edit mail", "Add mail"]);
+            assert_eq!(store.history(&vcs, "mail", 1).unwrap().len(), 1);
+            assert!(store.history(&vcs, "bank", 10).unwrap().is_empty());
+
+            // unknown revisions and entries fail before anything is decrypted
+            assert!(store.read_rev(&vcs, "mail", "0123456").is_err());
+            let first = &history[1].id;
+            assert!(store.read_rev(&vcs, "shop", first).is_err());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        #[ignore = "needs gpg and gpgme, changes GNUPGHOME"]
+        fn test_restore() {
+            let home = temp_dir("restore");
+            git_init(&home);
+            fs::write(home.join(".gpg-id"), format!("{}\n", gpg_key())).unwrap();
+            let vcs = vcs::from_path(home.to_str().unwrap()).unwrap();
+
+            let mut store = PassStore::from(&home).unwrap();
+            let old = Secret::from(String::from("old"));
+            store.insert(&vcs, "mail", &old).unwrap();
+            store.edit(&vcs, "mail", &Secret::from(String::from("new")))
+                .unwrap();
+
+            let history = store.history(&vcs, "mail", 10).unwrap();
+            assert_eq!(history.len(), 2);
+            let first = history[1].id.clone();
+            let content = store.read_rev(&vcs, "mail", &first).unwrap();
+            assert_eq!(content.as_str(), Some("old"));
+
+            store.restore(&vcs, "mail", &first).unwrap();
+            store.reload().unwrap();
+            let entry = store.get("mail").unwrap();
+            assert_eq!(store.read(&entry).unwrap().as_str(), Some("old"));
+            let history = store.history(&vcs, "mail", 10).unwrap();
+            assert_eq!(history.len(), 3);
+            assert_eq!(history[0].subject(),
+                       format!("Restore password mail from {}.", first));
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod transaction {
+        use std::fs;
+        use ::store::{PassStore, PassStoreError};
+        use ::testutil::{git, git_init, temp_dir};
+        use ::vcs;
+
+        #[test]
+        fn test_transaction() {
+            let home = temp_dir("transaction");
+            git_init(&home);
+            for name in &["a.gpg", "b.gpg", "c.gpg"] {
+                fs::write(home.join(name), name).unwrap();
+            }
+            git(&home, &["add", "."]);
+            git(&home, &["commit", "-q", "-m", "Initial"]);
+
+            let vcs = vcs::from_path(home.to_str().unwrap()).unwrap();
+            let mut store = PassStore::from(&home).unwrap();
+            store.transaction(&vcs, "Remove a and b", |store, vcs| {
+                for name in &["a", "b"] {
+                    let entry = store.get(*name).unwrap();
+                    store.remove(vcs, &entry)?;
+                }
+                Ok(())
+            }).unwrap();
+
+            let log = vcs.log(None, 10).unwrap();
+            assert_eq!(log.len(), 2);
+            assert!(log[0].message.starts_with("Remove a and b\n\n\
+                                                Remove a from store.\n\
+                                                Remove b from store.\n"));
+
+            // a failure restores and unstages c
+            let result: Result<(), _> =
+                store.transaction(&vcs, "Remove c", |store, vcs| {
+                    let entry = store.get("c").unwrap();
+                    store.remove(vcs, &entry)?;
+                    Err(PassStoreError::Other(String::from("abort")))
+                });
+            assert!(result.is_err());
+            assert_eq!(fs::read(home.join("c.gpg")).unwrap(), b"c.gpg");
+            assert!(store.get("c").is_some());
+            assert!(vcs.status().unwrap().is_empty());
+            assert_eq!(vcs.log(None, 10).unwrap().len(), 2);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod sync {
+        use std::fs;
+        use std::path::Path;
+        use ::store::PassStore;
+        use ::testutil::{git, git_clone, temp_dir};
+        use ::vcs;
+
+        fn clone(root: &Path, name: &str) -> PassStore {
+            PassStore::from(&git_clone(root, name)).unwrap()
+        }
+
+        fn add(dir: &Path, name: &str) {
+            fs::write(dir.join(name), name).unwrap();
+            git(dir, &["add", name]);
+            git(dir, &["commit", "-q", "-m", name]);
+        }
+
+        #[test]
+        fn test_sync() {
+            let root = temp_dir("sync");
+            git(&root, &["init", "-q", "--bare", "remote.git"]);
+
+            let mut a = clone(&root, "a");
+            let (dir_a, dir_b) = (root.join("a"), root.join("b"));
+            add(&dir_a, "foo.gpg");
+            git(&dir_a, &["push", "-q", "-u", "origin", "HEAD"]);
+            let mut b = clone(&root, "b");
+            let vcs_a = vcs::from_path(dir_a.to_str().unwrap()).unwrap();
+            let vcs_b = vcs::from_path(dir_b.to_str().unwrap()).unwrap();
+
+            add(&dir_a, "bar.gpg");
+            add(&dir_b, "baz.gpg");
+            let no_conflicts = |_: &_| panic!("unexpected conflict");
+            assert_eq!(a.sync(&vcs_a, no_conflicts).unwrap(), 0);
+            assert_eq!(b.sync(&vcs_b, no_conflicts).unwrap(), 0);
+            assert!(b.get("bar").is_some());
+            assert_eq!(a.sync(&vcs_a, no_conflicts).unwrap(), 0);
+            assert!(a.get("baz").is_some());
+            assert_eq!(vcs_a.log(None, 10).unwrap().len(), 3);
+
+            // nothing is pulled into uncommitted changes
+            fs::write(dir_a.join("foo.gpg"), "changed").unwrap();
+            let err = a.sync(&vcs_a, no_conflicts).unwrap_err();
+            assert!(err.to_string().contains("uncommitted changes of foo.gpg"));
+            assert_eq!(vcs_a.log(None, 10).unwrap().len(), 3);
+
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    mod message {
+        use std::fs;
+        use ::store::{format_message, PassStore};
+        use ::testutil::{git_init, temp_dir};
+        use ::util;
+        use ::vcs;
+
+        #[test]
+        fn test_format_message() {
+            let t = "[team] {operation} {entry} on {hostname}: {message} {x}";
+            assert_eq!(format_message(t, "remove", "web/mail", "Remove it."),
+                       format!("[team] remove web/mail on {}: Remove it. {{x}}",
+                               util::hostname()));
+            assert_eq!(format_message("{message}", "add", "foo", "{entry}"),
+                       "{entry}");
+            assert_eq!(format_message("{{entry}", "add", "foo", ""), "{foo");
+        }
+
+        #[test]
+        fn test_message_template() {
+            let home = temp_dir("message");
+            git_init(&home);
+            let vcs = vcs::from_path(home.to_str().unwrap()).unwrap();
+            for name in &["foo.gpg", "bar.gpg", "baz.gpg"] {
+                fs::write(home.join(name), "").unwrap();
+                vcs.add(name).unwrap();
+            }
+            vcs.commit("Add given passwords to store.").unwrap();
+
+            let mut store = PassStore::from(&home).unwrap();
+            store.set_message_template(String::from("[team] {operation} {entry}"));
+            let entry = store.get("foo").unwrap();
+            store.remove(&vcs, &entry).unwrap();
+            assert_eq!(vcs.log(None, 1).unwrap()[0].subject(),
+                       "[team] remove foo");
+
+            // the single changes of a transaction are formatted as well
+            store.transaction(&vcs, "Clean up", |store, vcs| {
+                for name in &["bar", "baz"] {
+                    let entry = store.get(*name).unwrap();
+                    store.remove(vcs, &entry)?;
+                }
+                Ok(())
+            }).unwrap();
+            assert!(vcs.log(None, 1).unwrap()[0].message
+                    .starts_with("[team] transaction \n\n[team] remove bar\n\
+                                  [team] remove baz\n"));
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod recording {
+        use std::fs;
+        use ::recording::{Call, CallKind, RecordingVcs};
+        use ::secret::Secret;
+        use ::store::PassStore;
+        use ::testutil::{gpg_key, temp_dir};
+        use ::vcs::VersionControl;
+
+        #[test]
+        fn test_insert() {
+            let home = temp_dir("recording-insert");
+
+            // without a .gpg-id nothing is encrypted, hence nothing committed
+            let recording = RecordingVcs::new();
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let mut store = PassStore::from(&home).unwrap();
+            let data = Secret::from(String::from("secret"));
+            assert!(store.insert(&vcs, "web/mail", &data).is_err());
+            assert!(!home.join("web/mail.gpg").exists());
+            assert!(recording.calls().is_empty());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        #[ignore = "needs gpg and gpgme, changes GNUPGHOME"]
+        fn test_insert_commit() {
+            let home = temp_dir("recording-insert-commit");
+            let fpr = gpg_key();
+            fs::write(home.join(".gpg-id"), format!("{}\n", fpr)).unwrap();
+            let path = |name: &str| home.join(name).to_str().unwrap().to_string();
+            let data = Secret::from(String::from("secret"));
+
+            let recording = RecordingVcs::new();
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let mut store = PassStore::from(&home).unwrap();
+            store.insert(&vcs, "web/mail", &data).unwrap();
+            assert!(home.join("web/mail.gpg").is_file());
+            assert_eq!(recording.calls(), vec![
+                Call::Add(path("web/mail.gpg")),
+                Call::Commit(String::from("Add given password web/mail to store.")),
+            ]);
+
+            // a failed commit is reported
+            let mut recording = RecordingVcs::new();
+            recording.fail_on(CallKind::Commit);
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            assert!(store.insert(&vcs, "web/shop", &data).is_err());
+            assert_eq!(recording.calls().len(), 2);
+
+            // nothing is committed if the entry cannot be staged
+            let mut recording = RecordingVcs::new();
+            recording.fail_on(CallKind::Add);
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            assert!(store.insert(&vcs, "web/bank", &data).is_err());
+            assert_eq!(recording.calls(), vec![Call::Add(path("web/bank.gpg"))]);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        #[ignore = "needs gpg and gpgme, changes GNUPGHOME"]
+        fn test_edit_creates() {
+            let home = temp_dir("recording-edit");
+            fs::write(home.join(".gpg-id"), format!("{}\n", gpg_key())).unwrap();
+
+            let recording = RecordingVcs::new();
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let mut store = PassStore::from(&home).unwrap();
+            assert!(store.get("web/new").is_none());
+            let data = Secret::from(String::from("secret"));
+            store.edit(&vcs, "web/new", &data).unwrap();
+            assert!(home.join("web/new.gpg").is_file());
+            let path = home.join("web/new.gpg").to_str().unwrap().to_string();
+            assert_eq!(recording.calls(), vec![
+                Call::Add(path),
+                Call::Commit(String::from("Edit password web/new in store.")),
+            ]);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        fn test_remove() {
+            let home = temp_dir("recording-remove");
+            for name in &["a.gpg", "b.gpg", "c.gpg"] {
+                fs::write(home.join(name), "").unwrap();
+            }
+            let path = |name: &str| home.join(name).to_str().unwrap().to_string();
+
+            let recording = RecordingVcs::new();
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let mut store = PassStore::from(&home).unwrap();
+            let entry = store.get("a").unwrap();
+            store.remove(&vcs, &entry).unwrap();
+            assert_eq!(recording.calls(), vec![
+                Call::Remove(path("a.gpg")),
+                Call::Commit(String::from("Remove a from store.")),
+            ]);
+
+            // a failed commit is reported
+            let mut recording = RecordingVcs::new();
+            recording.fail_on(CallKind::Commit);
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let entry = store.get("b").unwrap();
+            assert!(store.remove(&vcs, &entry).is_err());
+            assert_eq!(recording.calls().len(), 2);
+
+            // nothing is committed if the removal cannot be staged
+            let mut recording = RecordingVcs::new();
+            recording.fail_on(CallKind::Remove);
+            let vcs: Box<VersionControl> = Box::new(recording.clone());
+            let entry = store.get("c").unwrap();
+            assert!(store.remove(&vcs, &entry).is_err());
+            assert_eq!(recording.calls(), vec![Call::Remove(path("c.gpg"))]);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod dry_run {
+        use std::fs;
+        use ::lock::LOCK_FILE;
+        use ::ops::{Operation, Operations};
+        use ::store::PassStore;
+        use ::testutil::temp_dir;
+        use ::vcs::{NoVcs, VersionControl};
+
+        #[test]
+        fn test_remove() {
+            let home = temp_dir("dry-run");
+            fs::write(home.join("foo.gpg"), "").unwrap();
+
+            let ops = Operations::dry_run();
+            let vcs: Box<VersionControl> = Box::new(NoVcs{});
+            let mut store = PassStore::from(&home).unwrap();
+            store.set_operations(ops.clone());
+            let entry = store.get("foo").unwrap();
+            store.remove(&vcs, &entry).unwrap();
+            assert_eq!(ops.plan(), vec![Operation::RemoveFile(home.join("foo.gpg"))]);
+            assert!(home.join("foo.gpg").exists());
+            assert!(!home.join(LOCK_FILE).exists());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod lock {
+        use std::fs;
+        use ::lock::LOCK_FILE;
+        use ::store::PassStore;
+        use ::testutil::{git_init, temp_dir};
+
+        #[test]
+        fn test_lock_file() {
+            let root = temp_dir("lock-file").canonicalize().unwrap();
+            let home = root.join("team/store");
+            fs::create_dir_all(&home).unwrap();
+            let store = PassStore::from(&home).unwrap();
+            let path = |store: &PassStore| {
+                store.lock().unwrap().unwrap().path().to_path_buf()
+            };
+
+            // without a repository the lock file is within the store
+            assert_eq!(path(&store), home.join(LOCK_FILE));
+
+            // otherwise it is in the repository, also for a store within a
+            // subdirectory of it
+            fs::create_dir_all(root.join(".hg")).unwrap();
+            assert_eq!(path(&store), root.join(".hg").join(LOCK_FILE));
+            git_init(&root.join("team"));
+            assert_eq!(path(&store), root.join("team/.git").join(LOCK_FILE));
+
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    mod gpgid {
+        use std::fs;
+        use ::store::PassStore;
+        use ::testutil::temp_dir;
+
+        #[test]
+        fn test_gpgid_for() {
+            let home = temp_dir("gpgid");
+            fs::create_dir_all(home.join("team/db")).unwrap();
+            fs::write(home.join(".gpg-id"), "root@example.com\n").unwrap();
+            fs::write(home.join("team/.gpg-id"), "team@example.com\n").unwrap();
+
+            let store = PassStore::from(&home).unwrap();
+            assert_eq!(store.gpgid_for("foo").unwrap(), "root@example.com");
+            assert_eq!(store.gpgid_for("team/foo").unwrap(), "team@example.com");
+            assert_eq!(store.gpgid_for("team/db/foo").unwrap(),
+                       "team@example.com");
+            assert!(store.fsck().is_empty());
+
+            let mut store = store;
+            store.set_signing_keys(vec![String::from("0123 4567 89AB CDEF")]);
+            assert_eq!(store.fsck().len(), 2);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        fn test_verify_gpgid() {
+            let home = temp_dir("verify-gpgid");
+            let gpgid = home.join(".gpg-id");
+            fs::write(&gpgid, "root@example.com\n").unwrap();
+
+            // nothing is verified without signing keys
+            let mut store = PassStore::from(&home).unwrap();
+            assert!(store.verify_gpgid(&gpgid).is_ok());
+
+            store.set_signing_keys(vec![String::from("0123 4567 89AB CDEF")]);
+            let err = store.verify_gpgid(&gpgid).unwrap_err();
+            assert!(err.to_string().contains("does not exist"));
+            assert_eq!(store.fsck().len(), 1);
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+
+        #[test]
+        fn test_sign_gpgid() {
+            let home = temp_dir("sign-gpgid");
+            let gpgid = home.join(".gpg-id");
+            fs::write(&gpgid, "root@example.com\n").unwrap();
+
+            // nothing is signed without signing keys
+            let store = PassStore::from(&home).unwrap();
+            store.sign_gpgid(&gpgid).unwrap();
+            assert!(!home.join(".gpg-id.sig").exists());
+            assert!(store.verify_gpgid(&gpgid).is_ok());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    mod template {
+        use std::fs;
+        use ::store::PassStore;
+        use ::template::Template;
+        use ::testutil::temp_dir;
+
+        #[test]
+        fn test_template_for() {
+            let home = temp_dir("template");
+            fs::create_dir_all(home.join(".templates")).unwrap();
+            fs::create_dir_all(home.join("team/.templates/db")).unwrap();
+            fs::write(home.join(".templates/default"), "root").unwrap();
+            fs::write(home.join(".templates/api"), "api").unwrap();
+            fs::write(home.join("team/.templates/db/prod"), "prod").unwrap();
+
+            let store = PassStore::from(&home).unwrap();
+            let t = |e: &str| store.template_for(e).unwrap();
+            assert_eq!(t("foo"), Some(Template::new("root")));
+            assert_eq!(t("api/foo"), Some(Template::new("api")));
+            assert_eq!(t("team/db/prod/main"), Some(Template::new("prod")));
+            assert_eq!(t("team/db/test"), Some(Template::new("root")));
+            assert!(store.get(".templates").is_none());
+
+            fs::remove_dir_all(&home).unwrap();
+        }
+    }
 }
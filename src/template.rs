@@ -0,0 +1,122 @@
+//! Templates which pre-fill new entries of the store.
+//!
+//! Templates are plain text files in a `.templates` directory of the store.
+//! The following placeholders are replaced when a template is rendered:
+//!
+//! * `{{password}}` - a newly generated password, the same one for all
+//!   occurrences
+//! * `{{name}}` - the name of the entry
+//! * `{{date}}` - today's date as `YYYY-MM-DD`
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ::secret::Secret;
+
+pub static TEMPLATES_DIR: &'static str = ".templates";
+/// Name of the template which applies to all entries below its directory.
+pub static DEFAULT_TEMPLATE: &'static str = "default";
+
+static PLACEHOLDER_PASSWORD: &'static str = "{{password}}";
+static PLACEHOLDER_NAME: &'static str = "{{name}}";
+static PLACEHOLDER_DATE: &'static str = "{{date}}";
+
+/// A template for new entries, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    content: String,
+}
+
+impl Template {
+    pub fn new(content: &str) -> Template {
+        Template { content: content.to_string() }
+    }
+
+    /// Reads the template from the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Template> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+        Ok(Template { content: content })
+    }
+
+    /// Renders the template for the entry `name`. A password is only
+    /// generated, with `length` characters and `symbols` as given, if the
+    /// template contains the placeholder for it.
+    pub fn render(&self, name: &str, length: usize, symbols: bool)
+        -> io::Result<Secret>
+    {
+        let password = if self.content.contains(PLACEHOLDER_PASSWORD) {
+            Some(Secret::generate(length, symbols)?)
+        } else {
+            None
+        };
+        Ok(self.render_with(name, password.as_ref(), &today()))
+    }
+
+    fn render_with(&self, name: &str, password: Option<&Secret>, date: &str)
+        -> Secret
+    {
+        let pw = password.map(|p| p.as_bytes()).unwrap_or(b"");
+        // reserve the final size up front, hence the password is never left
+        // behind in a reallocated buffer
+        let count = self.content.matches(PLACEHOLDER_PASSWORD).count();
+        let mut out = Vec::with_capacity(
+            self.content.len() + count * pw.len() +
+            self.content.matches(PLACEHOLDER_NAME).count() * name.len() +
+            self.content.matches(PLACEHOLDER_DATE).count() * date.len());
+
+        let mut rest = self.content.as_str();
+        while let Some(start) = rest.find("{{") {
+            out.extend_from_slice(&rest.as_bytes()[..start]);
+            rest = &rest[start..];
+
+            let (value, placeholder): (&[u8], &str) =
+                if rest.starts_with(PLACEHOLDER_PASSWORD) {
+                    (pw, PLACEHOLDER_PASSWORD)
+                } else if rest.starts_with(PLACEHOLDER_NAME) {
+                    (name.as_bytes(), PLACEHOLDER_NAME)
+                } else if rest.starts_with(PLACEHOLDER_DATE) {
+                    (date.as_bytes(), PLACEHOLDER_DATE)
+                } else {
+                    (b"{{", "{{")
+                };
+            out.extend_from_slice(value);
+            rest = &rest[placeholder.len()..];
+        }
+        out.extend_from_slice(rest.as_bytes());
+
+        Secret::new(out)
+    }
+}
+
+/// Returns the current date (UTC) as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, m, d) = ::util::civil_from_days((secs / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Template;
+    use ::secret::Secret;
+
+    #[test]
+    fn test_render() {
+        let t = Template::new("{{password}}\nuser: {{name}}\n\
+                               created: {{date}}\nkeep: {{other}}\n");
+        let pw = Secret::from(String::from("s3cr3t"));
+        let s = t.render_with("db/main", Some(&pw), "2020-02-29");
+
+        assert_eq!(s.as_str(), Some("s3cr3t\nuser: db/main\n\
+                                     created: 2020-02-29\nkeep: {{other}}\n"));
+
+        let s = t.render("db/main", 12, false).unwrap();
+        assert_eq!(s.as_str().unwrap().lines().next().unwrap().len(), 12);
+    }
+}
@@ -0,0 +1,73 @@
+//! Helpers of the tests which work on a temporary store or git repository.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::OnceLock;
+
+/// Creates and returns the directory `rass-test-{name}-{pid}` in the
+/// temporary directory, the test removes it when done.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir()
+        .join(format!("rass-test-{}-{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `git` with `args` in `dir`, which has to succeed.
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git").args(args).current_dir(dir)
+        .status().unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// Creates a git repository in `dir` which commits as `rass`.
+pub fn git_init(dir: &Path) {
+    git(dir, &["init", "-q"]);
+    configure(dir, "rass");
+}
+
+/// Clones `remote.git` in `root` as `name`, which also commits as `name`.
+/// Returns the location of the clone.
+pub fn git_clone(root: &Path, name: &str) -> PathBuf {
+    git(root, &["clone", "-q", "remote.git", name]);
+    let dir = root.join(name);
+    configure(&dir, name);
+    dir
+}
+
+/// Sets the committer of the repository in `dir`, commits are not signed
+/// whatever the global git config says.
+fn configure(dir: &Path, name: &str) {
+    git(dir, &["config", "user.name", name]);
+    git(dir, &["config", "user.email", "rass@example.com"]);
+    git(dir, &["config", "commit.gpgsign", "false"]);
+}
+
+/// Returns the fingerprint of a key without passphrase, which is created once
+/// per test process in the GnuPG home `rass-test-gnupg-{pid}`. It becomes the
+/// `GNUPGHOME` of the whole process, hence tests using it are ignored by
+/// default.
+pub fn gpg_key() -> String {
+    static KEY: OnceLock<String> = OnceLock::new();
+    KEY.get_or_init(|| {
+        let gnupg = temp_dir("gnupg");
+        fs::set_permissions(&gnupg, fs::Permissions::from_mode(0o700)).unwrap();
+        env::set_var("GNUPGHOME", &gnupg);
+
+        let gpg = |args: &[&str]| {
+            let output = Command::new("gpg").arg("--batch").args(args)
+                .output().unwrap();
+            assert!(output.status.success(), "gpg {:?} failed", args);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        gpg(&["--passphrase", "", "--quick-gen-key", "rass <rass@example.com>",
+              "default", "default", "never"]);
+        gpg(&["--with-colons", "--list-keys"]).lines()
+            .find(|l| l.starts_with("fpr:"))
+            .and_then(|l| l.split(':').nth(9).map(String::from))
+            .unwrap()
+    }).clone()
+}
@@ -37,6 +37,15 @@ impl<'a, T> Path<T>
         }
     }
     
+    /// Creates a new `Path<T>` to an element which is a leaf of the tree if
+    /// `is_leaf` is set.
+    pub fn new(elements: vec::Vec<T>, is_leaf: bool) -> Path<T> {
+        Path {
+            elements: elements,
+            is_leaf: is_leaf,
+        }
+    }
+
     /// Returns the string representation of a `Path<T>`.
     pub fn to_string(&self) -> String {
         let mut r: Vec<u8> = vec![];
@@ -143,7 +152,7 @@ impl<'a, T> TreeVisitor<'a, T> for PathBuilder<T>
 /// * std::fmt::Display
 /// * std::clone::Clone
 /// * std::cmp::PartialEq
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Tree<T> where T: fmt::Display + cmp::PartialEq + clone::Clone
 {
     name: T,
@@ -200,6 +209,60 @@ impl<T> Tree<T> where T: fmt::Display + cmp::PartialEq + clone::Clone
         self.subs.is_empty()
     }
 
+    /// Returns the direct sub-tree with the given `name`, if any.
+    pub fn sub_mut(&mut self, name: &T) -> Option<&mut Tree<T>> {
+        self.subs.iter_mut().find(|x| x.name == *name)
+    }
+
+    /// Returns the path to the element which is reached by following `names`
+    /// from the direct sub-trees downwards, where `eq` compares an element
+    /// with a name. Only the sub-trees on the way are visited.
+    pub fn lookup<N, F>(&self, names: &[N], eq: F) -> Option<Path<T>>
+        where F: Fn(&T, &N) -> bool
+    {
+        let mut node = self;
+        let mut elements = Vec::with_capacity(names.len());
+        for n in names {
+            node = node.subs.iter().find(|x| eq(&x.name, n))?;
+            elements.push(node.name.clone());
+        }
+        Some(Path::new(elements, node.is_leaf()))
+    }
+
+    /// Returns a copy of the tree which only contains the elements on the
+    /// given `paths` (relative to the root) and everything below them.
+    pub fn pruned(&self, paths: &[Path<T>]) -> Tree<T> {
+        let paths: Vec<&Path<T>> = paths.iter().collect();
+        let depth = if self.is_root { 0 } else { 1 };
+        self.pruned_at(&paths, depth)
+    }
+
+    fn pruned_at(&self, paths: &[&Path<T>], depth: usize) -> Tree<T> {
+        let subs = self.subs
+            .iter()
+            .filter_map(|x| {
+                let paths: Vec<&Path<T>> = paths.iter()
+                    .filter(|p| p.elements.len() > depth &&
+                                p.elements[depth] == x.name)
+                    .cloned()
+                    .collect();
+                if paths.is_empty() {
+                    None
+                } else if paths.iter().any(|p| p.elements.len() == depth + 1) {
+                    Some(x.clone())
+                } else {
+                    Some(x.pruned_at(&paths, depth + 1))
+                }
+            })
+            .collect();
+
+        Tree {
+            name: self.name.clone(),
+            is_root: self.is_root,
+            subs: subs,
+        }
+    }
+
     /// Remove an element from the Tree as specified by the `path`. Returns 
     /// `true` if the element has been found and removed.
     pub fn remove(&mut self, path: &Path<T>) -> bool {
@@ -529,4 +592,41 @@ mod test {
         let paths: Vec<Path> = root.into_iter().collect();
         assert_eq!(paths.len(), 0);
     }
+
+    #[test]
+    fn tree_lookup() {
+        type Tree = super::Tree<String>;
+        let mut root = Tree::new("root".to_string());
+        let mut s1 = Tree::new("s1".to_string());
+        s1.add(Tree::new("s1_s1".to_string()));
+        root.add(s1);
+
+        let p = root.lookup(&["s1", "s1_s1"], |x, n| x == n).unwrap();
+        assert_eq!(p.to_string(), "s1/s1_s1");
+        assert!(p.is_leaf());
+        assert!(!root.lookup(&["s1"], |x, n| x == n).unwrap().is_leaf());
+        assert!(root.lookup(&["s1", "s2"], |x, n| x == n).is_none());
+    }
+
+    #[test]
+    fn tree_pruned() {
+        type Tree = super::Tree<String>;
+        type Path = super::Path<String>;
+        let mut root = Tree::new("root".to_string());
+        root.set_root(true);
+        let mut s1 = Tree::new("s1".to_string());
+        s1.add(Tree::new("s1_s1".to_string()));
+        s1.add(Tree::new("s1_s2".to_string()));
+        let mut s2 = Tree::new("s2".to_string());
+        s2.add(Tree::new("s2_s1".to_string()));
+        root.add(s1);
+        root.add(s2);
+        root.add(Tree::new("s3".to_string()));
+
+        let p = |e: &[&str]| Path::from(e.iter().map(|x| x.to_string()).collect());
+        let pruned = root.pruned(&[p(&["s1", "s1_s2"]), p(&["s2"])]);
+
+        let paths: Vec<String> = pruned.into_iter().map(|x| x.to_string()).collect();
+        assert_eq!(paths, vec!["s1", "s1/s1_s2", "s2", "s2/s2_s1"]);
+    }
 }
@@ -1,4 +1,10 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use libc;
+use tempfile::NamedTempFile;
 
 pub fn strip_path(full: &PathBuf, with: &PathBuf) -> PathBuf {
     let mut it_full = full.iter();
@@ -20,6 +26,85 @@ pub fn strip_path(full: &PathBuf, with: &PathBuf) -> PathBuf {
 }
 
 
+/// Writes the content of `data` to `path` in a crash-safe way. The content is
+/// written to a temporary file in the same directory, synced to disk and then
+/// renamed to `path`. Hence `path` either holds the old or the new content,
+/// never a truncated one. The parent directory has to exist.
+pub fn write_atomic<R: Read>(path: &Path, data: &mut R) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "path has no parent directory")),
+    };
+
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    io::copy(data, &mut tmp)?;
+    tmp.sync_all()?;
+    tmp.persist(path)?;
+
+    // make the rename itself durable
+    File::open(dir)?.sync_all()
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date of the
+/// proleptic Gregorian calendar.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// Removes the whitespace of a key fingerprint and converts it to upper case,
+/// so that fingerprints can be compared.
+pub fn normalize_fingerprint(fpr: &str) -> String {
+    fpr.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Returns the host name of the machine, empty if it is not available.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let res = unsafe {
+        libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    };
+    if res != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[test]
+fn test_write_atomic() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let dir = env::temp_dir()
+        .join(format!("rass-test-atomic-{}", process::id()));
+    let path = dir.join("sub/file.gpg");
+    assert!(write_atomic(&path, &mut "old".as_bytes()).is_err());
+
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    write_atomic(&path, &mut "old".as_bytes()).unwrap();
+    write_atomic(&path, &mut "new".as_bytes()).unwrap();
+
+    let mut content = String::new();
+    File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+    assert_eq!(content, "new");
+    assert_eq!(fs::read_dir(dir.join("sub")).unwrap().count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
 
 #[test]
 fn test_strip_path() {
@@ -31,3 +116,9 @@ fn test_strip_path() {
     assert_eq!(strip_path(&full2, &with).to_str(), Some("dir/file"));
 }
 
+#[test]
+fn test_civil_from_days() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+}
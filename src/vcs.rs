@@ -1,9 +1,201 @@
-use std::process::{Command,ExitStatus,Stdio};
-use std::os::unix::process::ExitStatusExt;
+use std::process::{Command,ExitStatus,Output,Stdio};
+use std::error;
+use std::fmt;
+use std::fs;
 use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::result;
 
+use git2;
+use gpgme;
+
+use ::config::{self, Config};
+use ::lock::LOCK_FILE;
+use ::ops::{Operation, Operations};
+
+pub static GITATTRIBUTES_FILE: &'static str = ".gitattributes";
+pub static GITIGNORE_FILE: &'static str = ".gitignore";
+/// Attribute which selects the `gpg` diff driver for the gpg-files.
+static GPG_DIFF_ATTRIBUTE: &'static str = "*.gpg diff=gpg";
+/// Textconv of the `gpg` diff driver, the same as the one of pass(1).
+static GPG_TEXTCONV: &'static str = "gpg -d --quiet --yes --compress-algo=none \
+                                     --no-encrypt-to --batch --use-agent";
+
+/// Files which are committed besides gpg-files and templates, see
+/// `rejected_files`.
+static ALLOWED_FILES: &'static [&'static str] = &[
+    ".gpg-id", ".gpg-id.sig", ".gitattributes", ".gitignore", ".rass.toml",
+];
+
+/// Header of an ASCII armored OpenPGP message.
+static ARMOR_HEADER: &'static str = "-----BEGIN PGP MESSAGE-----";
+
+/// Packets which start an OpenPGP message, as tag and the valid values of the
+/// first octet of the body (the version, or the algorithm of compressed data),
+/// see `is_openpgp`. Any value is valid for an empty list.
+static MESSAGE_PACKETS: &'static [(u8, &'static [u8])] = &[
+    (1, &[3, 6]),       // public-key encrypted session key
+    (3, &[4, 5, 6]),    // symmetric-key encrypted session key
+    (8, &[0, 1, 2, 3]), // compressed data
+    (9, &[]),           // symmetrically encrypted data
+    (18, &[1, 2]),      // symmetrically encrypted integrity protected data
+];
+
+/// Marks the pre-commit hook installed by `install_hook`.
+static HOOK_MARKER: &'static str = "# pre-commit hook installed by rass";
+
+/// Trailer which marks commits made by rass, see `is_rass_commit`.
+pub static RASS_TRAILER: &'static str = "Rass-Commit";
+
+/// Subjects of commits made by rass before they were marked by the trailer,
+/// as prefix and suffix.
+static RASS_SUBJECTS: &'static [(&'static str, &'static str)] = &[
+    ("Add given password ", " to store."),
+    ("Edit password ", " in store."),
+    ("Remove ", " from store."),
+    ("Rebuild search index.", ""),
+];
+
+/// A commit of the version control.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub id: String,
+    pub author: String,
+    /// Date of the commit in ISO 8601 format.
+    pub date: String,
+    /// The complete message including trailers.
+    pub message: String,
+}
+
+impl Commit {
+    /// Returns the first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
 
+#[derive(Debug)]
+pub enum VcsError {
+    Io(io::Error),
+    Git(git2::Error),
+    /// A command exited with a non-zero status, with the command line (the
+    /// program and its subcommand) and the stderr of the command.
+    Command(String, ExitStatus, String),
+    /// A revert conflicts with later changes of the given paths.
+    Conflict(Vec<String>),
+    /// Nothing is staged for a commit.
+    NothingToCommit,
+    /// The store is not within a repository.
+    NoRepository(PathBuf),
+    /// The operation is not supported by the backend, given by its program,
+    /// or without version control (`None`).
+    Unsupported(&'static str, Option<&'static str>),
+    /// Pulled commits which are not signed by a trusted key, as abbreviated
+    /// id and subject.
+    Untrusted(Vec<String>),
+    /// Staged files which must not be committed, see `rejected_files`.
+    Rejected(Vec<String>),
+}
+
+pub type Result<T> = result::Result<T, VcsError>;
+
+impl From<io::Error> for VcsError {
+    fn from(err: io::Error) -> VcsError {
+        VcsError::Io(err)
+    }
+}
+
+impl From<git2::Error> for VcsError {
+    fn from(err: git2::Error) -> VcsError {
+        VcsError::Git(err)
+    }
+}
+
+impl fmt::Display for VcsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VcsError::Io(ref err) => write!(f, "IO error: {}", err),
+            VcsError::Git(ref err) => write!(f, "Git error: {}", err.message()),
+            VcsError::Command(ref cmd, ref status, ref stderr) => {
+                write!(f, "'{}' failed ({})", cmd, status)?;
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            },
+            VcsError::Conflict(ref paths) =>
+                write!(f, "Conflicting changes of {}", paths.join(", ")),
+            VcsError::NothingToCommit => write!(f, "Nothing to commit"),
+            VcsError::NoRepository(ref path) =>
+                write!(f, "{:?} is not within a repository", path),
+            VcsError::Unsupported(op, Some(program)) =>
+                write!(f, "{} is not supported by the {} backend", op, program),
+            VcsError::Unsupported(op, None) =>
+                write!(f, "{} is not supported without version control", op),
+            VcsError::Untrusted(ref commits) =>
+                write!(f, "Commits not signed by a trusted key: {}",
+                       commits.join(", ")),
+            VcsError::Rejected(ref files) =>
+                write!(f, "Refusing to commit files which are not encrypted \
+                           or not within the store: \
+                           {}", files.join(", ")),
+        }
+    }
+}
+
+impl error::Error for VcsError {
+    fn description(&self) -> &str {
+        match *self {
+            VcsError::Io(ref err) => err.description(),
+            VcsError::Git(ref err) => err.message(),
+            VcsError::Command(..) => "command failed",
+            VcsError::Conflict(_) => "conflict",
+            VcsError::NothingToCommit => "nothing to commit",
+            VcsError::NoRepository(_) => "no repository",
+            VcsError::Unsupported(op, _) => op,
+            VcsError::Untrusted(_) => "untrusted commits",
+            VcsError::Rejected(_) => "rejected files",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            VcsError::Io(ref err) => Some(err),
+            VcsError::Git(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A file which was changed differently by a local and a remote commit, see
+/// `VersionControl::pull`. The versions are `None` if the file does not exist
+/// in them, e.g. if it was removed on one side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    /// Path of the file, relative to the store.
+    pub path: String,
+    /// The version of the common ancestor.
+    pub base: Option<Vec<u8>>,
+    pub local: Option<Vec<u8>>,
+    pub remote: Option<Vec<u8>>,
+}
+
+/// Returns whether the commit `message` was created by rass, either marked by
+/// the `RASS_TRAILER` or in one of the formats rass used before.
+pub fn is_rass_commit(message: &str) -> bool {
+    let trailer = format!("{}:", RASS_TRAILER);
+    if message.lines().any(|l| l.starts_with(&trailer)) {
+        return true;
+    }
+    let subject = message.lines().next().unwrap_or("");
+    RASS_SUBJECTS.iter().any(|&(prefix, suffix)| {
+        subject.starts_with(prefix) && subject.ends_with(suffix)
+    })
+}
+
+/// Version control of stores without any, used if it is disabled by the
+/// `vcs` setting. Changes are not committed, history is not available.
 #[derive(Debug)]
 pub struct NoVcs;
 
@@ -13,109 +205,2095 @@ impl VersionControl for NoVcs { }
 pub struct GitWrapper {
     repo: String,
     sign: bool,
+    /// Author of the commits as `Name <email>`, from `pass.author`.
+    author: Option<String>,
+    /// Fingerprints of the keys which have to sign pulled commits.
+    signers: Vec<String>,
+    /// Patterns of further files which may be committed.
+    allowed: Vec<String>,
+    push: bool,
+    ops: Operations,
+}
+
+/// Mercurial backend, which runs the `hg` command. Used for stores within a
+/// Mercurial repository, see `from_config`. Only committing is supported,
+/// hence nothing is pushed, also with `auto_push`.
+#[derive(Debug)]
+pub struct HgWrapper {
+    repo: String,
+    /// Patterns of further files which may be committed.
+    allowed: Vec<String>,
+    ops: Operations,
+}
+
+/// Git backend based on libgit2, which works without the `git` command.
+/// Only `cmd_dispatch` and the operations which need the network (`pull`,
+/// `push`) run the `git` command.
+pub struct Git2Wrapper {
+    repo: git2::Repository,
+    /// The store, paths passed to the trait methods are relative to it.
+    store: PathBuf,
+    /// Location of the store relative to the working directory of `repo`.
+    prefix: PathBuf,
+    sign: bool,
+    author: Option<String>,
+    signers: Vec<String>,
+    allowed: Vec<String>,
+    push: bool,
+    ops: Operations,
+}
+
+/// Returns the `VersionControl` for the store given in `config`. The settings
+/// of the config (e.g. `auto_push`, `git_backend`) are applied to the backend.
+/// Stores within a Mercurial repository (detected by `.hg`) get a `HgWrapper`.
+/// `NoVcs` is only returned if the `vcs` setting disables version control,
+/// otherwise a store outside of a repository is an error.
+pub fn from_config(config: &Config) -> Result<Box<VersionControl>> {
+    if config.vcs == config::VCS_NONE {
+        return Ok(Box::new(NoVcs{}));
+    }
+
+    let repo_path = config.store.to_str().unwrap_or("");
+    if is_hg_repo(&config.store) {
+        let mut hg = HgWrapper::new(repo_path);
+        hg.allowed = config.allowed_files.clone();
+        return Ok(Box::new(hg));
+    }
+    if config.git_backend == config::GIT_BACKEND_COMMAND {
+        if !is_git_repo(repo_path) {
+            return Err(VcsError::NoRepository(config.store.clone()));
+        }
+        let mut git = GitWrapper::new(repo_path);
+        git.push = config.auto_push;
+        git.signers = signers(config);
+        git.allowed = config.allowed_files.clone();
+        return Ok(Box::new(git));
+    }
+
+    match Git2Wrapper::open(&config.store) {
+        Ok(mut git) => {
+            git.push = config.auto_push;
+            git.signers = signers(config);
+            git.allowed = config.allowed_files.clone();
+            Ok(Box::new(git))
+        },
+        Err(ref e) if e.code() == git2::ErrorCode::NotFound =>
+            Err(VcsError::NoRepository(config.store.clone())),
+        Err(e) => Err(VcsError::Git(e)),
+    }
+}
+
+pub fn from_path(repo_path: &str) -> Result<Box<VersionControl>> {
+    let config = Config {
+        store: PathBuf::from(repo_path),
+        ..Config::default()
+    };
+    from_config(&config)
+}
+
+/// Creates a git repository for the store given in `config` and sets up the
+/// `gpg` diff driver, see `init_diff_driver`. An existing repository is
+/// reinitialized. The returned backend has `.gitattributes` and `.gitignore`
+/// staged, the initial commit is left to the caller.
+pub fn init(config: &Config, ops: Operations) -> Result<Box<VersionControl>> {
+    let store = &config.store;
+    let mut git = GitWrapper::new(store.to_str().unwrap_or(""));
+    git.push = config.auto_push;
+    git.signers = signers(config);
+    git.allowed = config.allowed_files.clone();
+    git.ops = ops;
+    git.run(Command::new("git").arg("init").arg("--quiet"))?;
+    init_diff_driver(store, &git.ops)?;
+    git.add(GITATTRIBUTES_FILE)?;
+    // the lock file is within the store as long as it has no repository
+    add_line(&store.join(GITIGNORE_FILE), &format!("/{}", LOCK_FILE),
+             &git.ops)?;
+    git.add(GITIGNORE_FILE)?;
+
+    // the repository does not exist in dry-run mode
+    if git.ops.is_dry_run() || config.git_backend == config::GIT_BACKEND_COMMAND {
+        return Ok(Box::new(git));
+    }
+    let mut git2 = Git2Wrapper::open(store)?;
+    git2.push = config.auto_push;
+    git2.signers = git.signers;
+    git2.allowed = git.allowed;
+    git2.ops = git.ops;
+    Ok(Box::new(git2))
+}
+
+/// Installs a pre-commit hook in the git repository of the store given in
+/// `config`, which refuses the same files as the built-in check of rass (see
+/// `rejected_files`) for commits made by `git` itself. A hook which was not
+/// installed by rass is not replaced. Returns the location of the hook.
+pub fn install_hook(config: &Config, ops: &Operations) -> Result<PathBuf> {
+    let repo = git2::Repository::discover(&config.store)?;
+    let prefix = store_prefix(&repo, &config.store)?;
+    let path = repo.path().join("hooks").join("pre-commit");
+    if let Ok(content) = fs::read_to_string(&path) {
+        if !content.contains(HOOK_MARKER) {
+            let s = format!("{:?} exists already", path);
+            return Err(VcsError::Io(io::Error::new(io::ErrorKind::AlreadyExists,
+                                                   s)));
+        }
+    }
+
+    let hook = pre_commit_hook(&prefix, &config.allowed_files);
+    ops.write(&path, &mut hook.as_bytes())?;
+    if !ops.is_dry_run() {
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
 }
 
-pub fn from_path(repo_path: &str) -> Box<VersionControl> {
-    let r =  Command::new("git").arg("-C")
+/// Returns the shell script of the pre-commit hook for the store at `prefix`
+/// of the repository, see `install_hook`.
+fn pre_commit_hook(prefix: &Path, allowed: &[String]) -> String {
+    let mut prefix = prefix.to_string_lossy().into_owned();
+    if !prefix.is_empty() {
+        prefix.push('/');
+    }
+    let names: Vec<String> = ALLOWED_FILES.iter().map(|f| sh_quote(f))
+        .chain(allowed.iter().filter(|p| !p.contains('/'))
+               .map(|p| sh_pattern(p)))
+        .collect();
+    let paths: Vec<String> = allowed.iter().filter(|p| p.contains('/'))
+        .map(|p| sh_pattern(p))
+        .collect();
+    let packets: Vec<String> = MESSAGE_PACKETS.iter()
+        .flat_map(|&(tag, values)| {
+            if values.is_empty() {
+                vec![format!("{}:*", tag)]
+            } else {
+                values.iter().map(|v| format!("{}:{}", tag, v)).collect()
+            }
+        })
+        .collect();
+    let path_case = if paths.is_empty() {
+        String::new()
+    } else {
+        format!("        case \"$file\" in\n            {}) continue ;;\n        \
+                 esac\n", paths.join("|"))
+    };
+
+    format!("#!/bin/sh
+{marker}, refuses files of the store
+# which are not encrypted, like the check of rass itself.
+prefix={prefix}
+
+# whether the given octets start an OpenPGP message, see is_openpgp of rass
+is_message() {{
+    if [ \"$1\" -ge 192 ]; then
+        tag=$(($1 & 63))
+        if [ \"${{2:-0}}\" -ge 192 ] && [ \"${{2:-0}}\" -lt 224 ]; then
+            n=2
+        elif [ \"${{2:-0}}\" -eq 255 ]; then
+            n=5
+        else
+            n=1
+        fi
+    elif [ \"$1\" -ge 128 ]; then
+        tag=$((($1 >> 2) & 15))
+        case $(($1 & 3)) in
+            0) n=1 ;;
+            1) n=2 ;;
+            2) n=4 ;;
+            *) n=0 ;;
+        esac
+    else
+        return 1
+    fi
+    eval \"body=\\${{$((n + 2)):-}}\"
+    case \"$tag:$body\" in
+        {packets}) return 0 ;;
+    esac
+    return 1
+}}
+
+git -c core.quotepath=off diff --cached --name-only --diff-filter=ACMRT \\
+    -- \"./$prefix\" | {{
+    status=0
+    while IFS= read -r path; do
+        file=${{path#\"$prefix\"}}
+        case \"$file\" in
+            {templates}/*|*/{templates}/*) continue ;;
+            *.gpg)
+                header=$(git cat-file blob \":$path\" | head -c 27 |
+                         tr -d '\\000')
+                bytes=$(git cat-file blob \":$path\" | head -c 7 | od -An -tu1)
+                if [ -n \"$bytes\" ] && ! is_message $bytes &&
+                   [ \"$header\" != '{armor}' ]; then
+                    echo \"rass: $file is not an OpenPGP message\" >&2
+                    status=1
+                fi
+                continue ;;
+        esac
+        case \"${{file##*/}}\" in
+            {names}) continue ;;
+        esac
+{path_case}        echo \"rass: $file is not encrypted, refusing to commit it\" >&2
+        status=1
+    done
+    exit $status
+}}
+",
+            marker = HOOK_MARKER,
+            prefix = sh_quote(&prefix),
+            templates = sh_quote(::template::TEMPLATES_DIR),
+            armor = ARMOR_HEADER,
+            packets = packets.join("|"),
+            names = names.join("|"),
+            path_case = path_case)
+}
+
+/// Quotes `s` for the shell.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Quotes the glob `pattern` for a shell `case`, `*` and `?` keep their
+/// meaning.
+fn sh_pattern(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        if c == '*' || c == '?' {
+            if !literal.is_empty() {
+                result.push_str(&sh_quote(&literal));
+                literal.clear();
+            }
+            result.push(c);
+        } else {
+            literal.push(c);
+        }
+    }
+    if !literal.is_empty() {
+        result.push_str(&sh_quote(&literal));
+    }
+    result
+}
+
+/// Returns the normalized fingerprints of `commit_signers` in `config`.
+fn signers(config: &Config) -> Vec<String> {
+    config.commit_signers.iter()
+        .map(|k| ::util::normalize_fingerprint(k))
+        .collect()
+}
+
+/// Marks the gpg-files as `diff=gpg` in the `.gitattributes` of `store` and
+/// configures the textconv of the driver in the local git config, so that
+/// `git log -p` and `git diff` show the decrypted changes.
+fn init_diff_driver(store: &Path, ops: &Operations) -> Result<()> {
+    add_line(&store.join(GITATTRIBUTES_FILE), GPG_DIFF_ATTRIBUTE, ops)?;
+
+    for &(key, value) in &[("diff.gpg.binary", "true"),
+                           ("diff.gpg.textconv", GPG_TEXTCONV)] {
+        let mut cmd = Command::new("git");
+        cmd.args(["config", "--local", key, value]).current_dir(store);
+        let output = ops.output(&mut cmd)?;
+        check(&cmd, &output)?;
+    }
+    Ok(())
+}
+
+/// Appends `line` to the file at `path`, unless it contains the line already.
+fn add_line(path: &Path, line: &str, ops: &Operations) -> Result<()> {
+    let mut content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(VcsError::Io(e)),
+    };
+    if !content.lines().any(|l| l.trim() == line) {
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(line);
+        content.push('\n');
+        ops.write(path, &mut content.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Returns the directory of the repository which contains `store`, e.g. its
+/// `.git` or `.hg`, where files of rass stay out of the working tree. `None`
+/// if the store is not within a repository.
+pub fn repository_dir(store: &Path) -> Option<PathBuf> {
+    let store = store.canonicalize().unwrap_or_else(|_| store.to_path_buf());
+    for dir in store.ancestors() {
+        // `.git` is a file in worktrees and submodules
+        if dir.join(".git").exists() {
+            return git2::Repository::open(dir).ok()
+                .map(|repo| repo.path().to_path_buf());
+        }
+        if dir.join(".hg").is_dir() {
+            return Some(dir.join(".hg"));
+        }
+    }
+    None
+}
+
+/// Returns whether `store` is within a Mercurial repository, i.e. the nearest
+/// repository found from `store` upwards has a `.hg` rather than a `.git`.
+fn is_hg_repo(store: &Path) -> bool {
+    let store = store.canonicalize().unwrap_or_else(|_| store.to_path_buf());
+    for dir in store.ancestors() {
+        if dir.join(".git").exists() {
+            return false;
+        }
+        if dir.join(".hg").is_dir() {
+            return true;
+        }
+    }
+    false
+}
+
+fn is_git_repo(repo_path: &str) -> bool {
+    Command::new("git").arg("-C")
             .arg(&repo_path)
             .arg("rev-parse")
             .arg("--is-inside-work-tree")
             .stderr(Stdio::null())
             .stdout(Stdio::null())
             .status()
-            .expect("git command not found.").success();
-
-    if r {
-        Box::new(GitWrapper::new(repo_path))
-    } else {
-        println!("'{}' is not a git repo, no vcs support!", repo_path);
-        Box::new(NoVcs{})
-    }
+            .map(|s| s.success())
+            .unwrap_or(false)
 }
 
-pub type Result<T> = result::Result<T, io::Error>;
-
 /// Version control trait. Note that `add` and `remove` will not commit the
-/// operation. Hence `commit` has to be called separatly.
+/// operation. Hence `commit` has to be called separatly. The default
+/// implementations are the ones of `NoVcs`: changes are accepted without being
+/// recorded, everything else is `VcsError::Unsupported`.
 pub trait VersionControl {
-    fn add(&self, _file: &str) -> Result<ExitStatus> {
-        Ok(ExitStatus::from_raw(0))
+    fn add(&self, _file: &str) -> Result<()> {
+        Ok(())
+    }
+    fn remove(&self, _file: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Commits all staged changes, fails with `VcsError::NothingToCommit` if
+    /// there are none.
+    fn commit(&self, _message: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Runs a command of the version control with `args`, its output is not
+    /// captured.
+    fn cmd_dispatch(&self, _args: Vec<&str>) -> Result<()> {
+        Err(VcsError::Unsupported("running commands", self.program()))
+    }
+    /// Returns the command run by `cmd_dispatch`, e.g. `git`.
+    fn program(&self) -> Option<&'static str> {
+        None
+    }
+    /// Returns up to `max` commits, newest first. If `path` is given, only
+    /// commits which changed it are returned.
+    fn log(&self, _path: Option<&str>, _max: usize) -> Result<Vec<Commit>> {
+        Err(VcsError::Unsupported("history", self.program()))
+    }
+    /// Returns the content of the file at `path` (relative to the repository)
+    /// in revision `rev`.
+    fn file_at(&self, _rev: &str, _path: &str) -> Result<Vec<u8>> {
+        Err(VcsError::Unsupported("history", self.program()))
+    }
+    /// Reverts the changes of commit `id` in the working tree and the index,
+    /// without committing.
+    fn revert(&self, _id: &str) -> Result<()> {
+        Err(VcsError::Unsupported("revert", self.program()))
     }
-    fn remove(&self, _file: &str) -> Result<ExitStatus> {
-        Ok(ExitStatus::from_raw(0))
+    /// Returns the paths (relative to the store) of all files with changes
+    /// which are not committed yet, including untracked files.
+    fn status(&self) -> Result<Vec<String>> {
+        Err(VcsError::Unsupported("status", self.program()))
     }
-    fn commit(&self, _message: &str) -> Result<ExitStatus> {
-        Ok(ExitStatus::from_raw(0))
+    /// Fetches the remote and rebases the local commits onto it. Returns the
+    /// conflicts of the first local commit which does not apply cleanly, they
+    /// have to be resolved by `add` or `remove` and `continue_pull`.
+    fn pull(&self) -> Result<Vec<MergeConflict>> {
+        Err(VcsError::Unsupported("pull", self.program()))
     }
-    fn cmd_dispatch(&self, _args: Vec<&str>) -> Result<ExitStatus> {
-        Ok(ExitStatus::from_raw(0))
+    /// Continues a `pull` after the conflicts were resolved. Returns the
+    /// conflicts of the next local commit.
+    fn continue_pull(&self) -> Result<Vec<MergeConflict>> {
+        Err(VcsError::Unsupported("pull", self.program()))
     }
+    /// Aborts a `pull` with conflicts, the local commits are restored.
+    fn abort_pull(&self) -> Result<()> {
+        Err(VcsError::Unsupported("pull", self.program()))
+    }
+    fn push(&self) -> Result<()> {
+        Err(VcsError::Unsupported("push", self.program()))
+    }
+    /// Sets the operation layer through which all modifying commands are
+    /// run, e.g. to record them in dry-run mode.
+    fn set_operations(&mut self, _ops: Operations) { }
 }
 
 impl GitWrapper {
     fn new(repo_path: &str) -> GitWrapper {
         let repo_path = String::from(repo_path);
 
-        let output = Command::new("git")
-                        .arg("config")
-                        .arg("--bool")
-                        .arg("--get")
-                        .arg("pass.signcommits")
-                        .current_dir(&repo_path)
-                        .output();
-
-        let sign = match output {
-            Ok(output) => {
-                match String::from_utf8_lossy(&output.stdout).trim() {
-                    "true" | "True" | "TRUE" => true,
-                    _ => false
-                }
-            },
-            Err(_) => false
+        let config = |args: &[&str]| {
+            Command::new("git")
+                .arg("config")
+                .args(args)
+                .current_dir(&repo_path)
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
         };
 
+        let sign = match config(&["--bool", "--get", "pass.signcommits"]) {
+            Some(ref v) => v == "true",
+            None => false
+        };
+        let author = config(&["--get", "pass.author"]);
+
         GitWrapper {
             repo: repo_path,
             sign: sign,
+            author: author,
+            signers: vec![],
+            allowed: vec![],
+            push: false,
+            ops: Operations::new(),
+        }
+    }
+}
+
+impl GitWrapper {
+    /// Runs the modifying `cmd` through the operations. stdout is passed
+    /// through, stderr is captured for the error of a non-zero exit.
+    fn run(&self, cmd: &mut Command) -> Result<()> {
+        cmd.current_dir(&self.repo)
+           .stdout(Stdio::inherit())
+           .stderr(Stdio::piped());
+        let output = self.ops.output(cmd)?;
+        check(cmd, &output)
+    }
+
+    /// Runs the reading `cmd` and returns its stdout.
+    fn read(&self, cmd: &mut Command) -> Result<Vec<u8>> {
+        let output = cmd.current_dir(&self.repo).output()?;
+        check(cmd, &output)?;
+        Ok(output.stdout)
+    }
+
+    /// Runs the rebase `cmd`, returns the conflicts if it stopped on them.
+    fn rebase(&self, cmd: &mut Command) -> Result<Vec<MergeConflict>> {
+        // keep the messages of the rebased commits
+        cmd.env("GIT_EDITOR", "true");
+        match self.run(cmd) {
+            Ok(()) => Ok(vec![]),
+            Err(err) => {
+                let conflicts = self.conflicts()?;
+                if conflicts.is_empty() {
+                    return Err(err);
+                }
+                Ok(conflicts)
+            },
+        }
+    }
+
+    fn conflicts(&self) -> Result<Vec<MergeConflict>> {
+        let output = self.read(Command::new("git")
+            .arg("diff")
+            .arg("--name-only")
+            .arg("--relative")
+            .arg("--diff-filter=U")
+            .arg("-z"))?;
+        let output = String::from_utf8_lossy(&output);
+
+        // while rebasing, stage 2 (ours) is the upstream and stage 3
+        // (theirs) the local commit
+        let stage = |n: u8, path: &str| {
+            self.read(Command::new("git")
+                .arg("show")
+                .arg(format!(":{}:./{}", n, path))
+                .stderr(Stdio::null())).ok()
+        };
+        Ok(output.split('\0')
+            .filter(|p| !p.is_empty())
+            .map(|p| MergeConflict {
+                path: p.to_string(),
+                base: stage(1, p),
+                local: stage(3, p),
+                remote: stage(2, p),
+            })
+            .collect())
+    }
+
+    /// Returns the commits in `from..to` which change entries or `.gpg-id`
+    /// files and are not signed by one of the `signers`. `from` is `None` if
+    /// all commits up to `to` are new, e.g. for an empty repository.
+    fn untrusted_commits(&self, from: Option<git2::Oid>, to: &str)
+        -> Result<Vec<String>>
+    {
+        let repo = git2::Repository::discover(&self.repo)?;
+        let mut walk = repo.revwalk()?;
+        walk.push(repo.revparse_single(to)?.peel_to_commit()?.id())?;
+        if let Some(from) = from {
+            walk.hide(from)?;
+        }
+
+        let mut untrusted = vec![];
+        for id in walk {
+            let commit = repo.find_commit(id?)?;
+            if changes_keys(&repo, &commit)?
+                && !is_signed_by(&repo, commit.id(), &self.signers)
+            {
+                untrusted.push(format!("{:.7} {}", commit.id(),
+                                       commit.summary().unwrap_or("")));
+            }
+        }
+        Ok(untrusted)
+    }
+
+    /// Fails with `VcsError::Untrusted` if any commit in `from..to` is
+    /// untrusted, see `untrusted_commits`. Nothing is verified without
+    /// `signers` or in dry-run mode.
+    fn verify_commits(&self, from: Option<git2::Oid>, to: &str) -> Result<()> {
+        if self.signers.is_empty() || self.ops.is_dry_run() {
+            return Ok(());
+        }
+        let untrusted = self.untrusted_commits(from, to)?;
+        if untrusted.is_empty() {
+            Ok(())
+        } else {
+            Err(VcsError::Untrusted(untrusted))
+        }
+    }
+
+    /// Returns the id of the current commit, `None` for an empty repository.
+    fn head_id(&self) -> Result<Option<git2::Oid>> {
+        let repo = git2::Repository::discover(&self.repo)?;
+        let head = match repo.head() {
+            Ok(head) => head,
+            Err(ref e) if e.code() == git2::ErrorCode::UnbornBranch =>
+                return Ok(None),
+            Err(e) => return Err(VcsError::Git(e)),
+        };
+        let id = head.peel_to_commit()?.id();
+        Ok(Some(id))
+    }
+
+    /// Runs `git` with `args`, its output is not captured.
+    fn dispatch(&self, args: &[&str]) -> Result<()> {
+        let mut cmd = Command::new("git");
+        cmd.args(args)
+           .current_dir(&self.repo);
+        let status = self.ops.status(&mut cmd)?;
+        if !status.success() {
+            return Err(VcsError::Command(command_line(&cmd), status,
+                                         String::new()));
+        }
+        Ok(())
+    }
+
+    /// Runs `git pull` with `args` as `git fetch`, the fetched commits are
+    /// merged (or rebased onto with `--rebase`) only if they are trusted.
+    fn verified_pull(&self, args: &[&str]) -> Result<()> {
+        let is_rebase = |a: &str| a == "-r" || a.starts_with("--rebase");
+        let is_merge = |a: &str| MERGE_OPTIONS.contains(&a);
+        let mut fetch = vec!["fetch"];
+        fetch.extend(args.iter().filter(|a| {
+            !is_rebase(a) && !is_merge(a) && **a != "--no-rebase"
+        }));
+        self.dispatch(&fetch)?;
+
+        let head = self.head_id()?;
+        // nothing is fetched in dry-run mode
+        let fetched = if self.ops.is_dry_run() {
+            vec![String::from("FETCH_HEAD")]
+        } else {
+            self.fetched_for_merge()?
+        };
+        for rev in &fetched {
+            self.verify_commits(head, rev)?;
+        }
+
+        // an empty branch cannot be rebased, the fetched commits are merged
+        let rebase = head.is_some() && args.iter().any(|a| is_rebase(a));
+        let mut merge = vec![if rebase { "rebase" } else { "merge" }];
+        merge.extend(args.iter().filter(|a| {
+            (!rebase && is_merge(a)) || **a == "-q" || **a == "--quiet"
+        }));
+        if rebase {
+            merge.push(fetched[0].as_str());
+        } else {
+            merge.extend(fetched.iter().map(String::as_str));
+        }
+        self.dispatch(&merge)
+    }
+
+    /// Returns the commits of the last `git fetch` which are to be merged,
+    /// as `git pull` does.
+    fn fetched_for_merge(&self) -> Result<Vec<String>> {
+        let repo = git2::Repository::discover(&self.repo)?;
+        let content = fs::read_to_string(repo.path().join("FETCH_HEAD"))?;
+        // lines are `<id>\t<not-for-merge>\t<description>`
+        let fetched: Vec<String> = content.lines()
+            .filter(|l| l.split('\t').nth(1) == Some(""))
+            .filter_map(|l| l.split('\t').next().map(String::from))
+            .collect();
+        if fetched.is_empty() {
+            let s = "no fetched branch to merge, the branch has no upstream";
+            return Err(VcsError::Io(io::Error::other(s)));
+        }
+        Ok(fetched)
+    }
+
+    /// Returns whether changes are staged.
+    fn has_staged(&self) -> Result<bool> {
+        let mut cmd = Command::new("git");
+        cmd.arg("diff")
+           .arg("--cached")
+           .arg("--quiet")
+           .current_dir(&self.repo)
+           .stderr(Stdio::piped());
+        let output = cmd.output()?;
+        match output.status.code() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => check(&cmd, &output).map(|_| false),
         }
     }
 }
 
 impl VersionControl for GitWrapper {
-    fn add(&self, file: &str) -> Result<ExitStatus> {
-        Command::new("git")
+    fn add(&self, file: &str) -> Result<()> {
+        self.run(Command::new("git")
             .arg("add")
-            .arg(file)
-            .current_dir(&self.repo)
-            .status()
+            .arg(file))
     }
 
-    fn commit(&self, message: &str) -> Result<ExitStatus> {
+    fn commit(&self, message: &str) -> Result<()> {
+        // staged changes are unknown in dry-run mode, since `add` is recorded
+        if !self.ops.is_dry_run() {
+            if !self.has_staged()? {
+                return Err(VcsError::NothingToCommit);
+            }
+            let repo = git2::Repository::discover(&self.repo)?;
+            let prefix = store_prefix(&repo, Path::new(&self.repo))?;
+            let rejected = rejected_files(&repo, &prefix, &self.allowed)?;
+            if !rejected.is_empty() {
+                return Err(VcsError::Rejected(rejected));
+            }
+        }
+
         let mut cmd = Command::new("git");
         cmd.arg("commit")
            .arg("-m")
            .arg(message)
-           .current_dir(&self.repo);
+           .arg("-m")
+           .arg(format!("{}: {}", RASS_TRAILER, env!("CARGO_PKG_VERSION")));
         if self.sign {
             cmd.arg("-S");
         }
-        cmd.status()
+        // git takes an author without email as pattern of a known author
+        if let Some(ref author) = self.author {
+            parse_author(author)?;
+            cmd.arg(format!("--author={}", author));
+        }
+        self.run(&mut cmd)?;
+
+        if self.push {
+            self.run(Command::new("git").arg("push"))?;
+        }
+        Ok(())
     }
 
-    fn remove(&self, file: &str) -> Result<ExitStatus> {
-        let mut cmd = Command::new("git");
-        cmd.arg("rm")
-           .arg("-qr")
-           .arg(file)
-           .current_dir(&self.repo);
-        cmd.status()
+    fn remove(&self, file: &str) -> Result<()> {
+        self.run(Command::new("git")
+            .arg("rm")
+            .arg("-qr")
+            .arg(file))
+    }
+
+    fn cmd_dispatch(&self, args: Vec<&str>) -> Result<()> {
+        // commits of the remote are verified before they are merged, hence
+        // they do not reach the working tree even if the merge conflicts
+        if !self.signers.is_empty() {
+            match args.first() {
+                Some(&"pull") => return self.verified_pull(&args[1..]),
+                Some(&"merge") | Some(&"rebase") => {
+                    let head = self.head_id()?;
+                    for rev in incoming_revisions(&args) {
+                        self.verify_commits(head, rev)?;
+                    }
+                },
+                _ => (),
+            }
+        }
+        self.dispatch(&args)
     }
 
-    fn cmd_dispatch(&self, args: Vec<&str>) -> Result<ExitStatus> {
+    fn log(&self, path: Option<&str>, max: usize) -> Result<Vec<Commit>> {
         let mut cmd = Command::new("git");
+        cmd.arg("log")
+           .arg(format!("--max-count={}", max))
+           .arg("--format=%H%x1f%an%x1f%aI%x1f%B%x1e");
+        if let Some(path) = path {
+            cmd.arg("--").arg(path);
+        }
+
+        let output = self.read(&mut cmd)?;
+        Ok(parse_log(&String::from_utf8_lossy(&output)))
+    }
+
+    fn file_at(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        // `./` makes the path relative to the store, not to the top level of
+        // the repository
+        self.read(Command::new("git")
+            .arg("show")
+            .arg(format!("{}:./{}", rev, path)))
+    }
+
+    fn revert(&self, id: &str) -> Result<()> {
+        self.run(Command::new("git")
+            .arg("revert")
+            .arg("--no-commit")
+            .arg(id))
+    }
+
+    fn status(&self) -> Result<Vec<String>> {
+        // porcelain paths are relative to the top level of the repository
+        let prefix = self.read(Command::new("git")
+            .arg("rev-parse")
+            .arg("--show-prefix"))?;
+        let prefix = String::from_utf8_lossy(&prefix).trim().to_string();
+
+        let output = self.read(Command::new("git")
+            .arg("status")
+            .arg("--porcelain")
+            .arg("--untracked-files=all")
+            .arg("-z")
+            .arg("."))?;
+
+        let output = String::from_utf8_lossy(&output);
+        let mut paths = vec![];
+        let mut records = output.split('\0');
+        while let Some(record) = records.next() {
+            if record.len() < 4 {
+                continue;
+            }
+            // renames are followed by a record with the original path
+            if record.starts_with('R') || record.starts_with('C') {
+                records.next();
+            }
+            let path = &record[3..];
+            paths.push(path.strip_prefix(prefix.as_str()).unwrap_or(path).to_string());
+        }
+        Ok(paths)
+    }
+
+    fn program(&self) -> Option<&'static str> {
+        Some("git")
+    }
+
+    fn pull(&self) -> Result<Vec<MergeConflict>> {
+        self.run(Command::new("git").arg("fetch"))?;
+        // the fetched commits are verified before they are used
+        self.verify_commits(self.head_id()?, "@{upstream}")?;
+        self.rebase(Command::new("git")
+            .arg("rebase")
+            .arg("@{upstream}"))
+    }
+
+    fn continue_pull(&self) -> Result<Vec<MergeConflict>> {
+        self.rebase(Command::new("git")
+            .arg("rebase")
+            .arg("--continue"))
+    }
+
+    fn abort_pull(&self) -> Result<()> {
+        self.run(Command::new("git")
+            .arg("rebase")
+            .arg("--abort"))
+    }
+
+    fn push(&self) -> Result<()> {
+        self.run(Command::new("git").arg("push"))
+    }
+
+    fn set_operations(&mut self, ops: Operations) {
+        self.ops = ops;
+    }
+}
+
+/// Options of `git pull` which are passed to `git merge` instead of
+/// `git fetch`.
+static MERGE_OPTIONS: &[&str] = &["--ff", "--no-ff", "--ff-only", "--squash",
+                                  "--no-squash", "--commit", "--no-commit",
+                                  "--edit", "--no-edit"];
+
+/// Returns the revisions whose commits `git merge` or `git rebase` with
+/// `args` (including the subcommand) bring into the branch, the upstream if
+/// none is given. Continuing or aborting brings in nothing new.
+fn incoming_revisions<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    const WITH_VALUE: &[&str] = &["-m", "-F", "-s", "-X", "-x", "--exec",
+                                  "--message", "--file", "--strategy",
+                                  "--strategy-option"];
+    const IN_PROGRESS: &[&str] = &["--abort", "--continue", "--skip", "--quit",
+                                   "--edit-todo", "--show-current-patch"];
+    // `git rebase <upstream> <branch>` rebases the local branch
+    let max = if args.first() == Some(&"rebase") { 1 } else { usize::MAX };
+
+    let mut revs = vec![];
+    let mut positional = 0;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if IN_PROGRESS.contains(arg) {
+            return vec![];
+        } else if *arg == "--onto" {
+            revs.extend(iter.next());
+        } else if let Some(rev) = arg.strip_prefix("--onto=") {
+            revs.push(rev);
+        } else if WITH_VALUE.contains(arg) {
+            iter.next();
+        } else if !arg.starts_with('-') {
+            if positional < max {
+                revs.push(*arg);
+            }
+            positional += 1;
+        }
+    }
+    if positional == 0 {
+        revs.push("@{upstream}");
+    }
+    revs
+}
+
+/// Returns whether `commit` changes gpg-files or `.gpg-id` files compared to
+/// its first parent.
+fn changes_keys(repo: &git2::Repository, commit: &git2::Commit)
+    -> Result<bool>
+{
+    let parent = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?),
+                                      None)?;
+    let is_key = |path: Option<&Path>| path.is_some_and(|p| {
+        p.extension().is_some_and(|e| e == "gpg")
+            || p.file_name().is_some_and(|n| n == ".gpg-id")
+    });
+    Ok(diff.deltas().any(|d| {
+        is_key(d.old_file().path()) || is_key(d.new_file().path())
+    }))
+}
+
+/// Returns whether commit `id` has a valid signature of one of the `signers`,
+/// the fingerprints of their primary keys.
+fn is_signed_by(repo: &git2::Repository, id: git2::Oid, signers: &[String])
+    -> bool
+{
+    let (signature, signed) = match repo.extract_signature(&id, None) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let mut ctx = match gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp) {
+        Ok(ctx) => ctx,
+        Err(_) => return false,
+    };
+    let result = match ctx.verify_detached(&*signature, &*signed) {
+        Ok(result) => result,
+        Err(_) => return false,
+    };
+
+    result.signatures().any(|sig| {
+        let fpr = match sig.fingerprint() {
+            Ok(fpr) if sig.status().is_ok() => fpr,
+            _ => return false,
+        };
+        // the signature might be made by a subkey
+        let primary = ctx.find_key(fpr).ok()
+            .and_then(|k| k.fingerprint().ok().map(String::from));
+        signers.iter().any(|k| {
+            *k == ::util::normalize_fingerprint(fpr) || Some(k) == primary.as_ref()
+        })
+    })
+}
+
+/// Returns the location of `store` relative to the working directory of
+/// `repo`.
+fn store_prefix(repo: &git2::Repository, store: &Path)
+    -> result::Result<PathBuf, git2::Error>
+{
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.canonicalize()
+            .map_err(|e| git2::Error::from_str(&e.to_string()))?,
+        None => return Err(git2::Error::from_str("bare repositories are \
+                                                  not supported")),
+    };
+    let store = store.canonicalize()
+        .map_err(|e| git2::Error::from_str(&e.to_string()))?;
+    Ok(store.strip_prefix(&workdir)
+        .map(Path::to_path_buf)
+        .unwrap_or_default())
+}
+
+/// Returns the staged files of the store at `prefix` which must not be
+/// committed, e.g. backup files of an editor. Only gpg-files with OpenPGP
+/// content, `ALLOWED_FILES`, templates and files matching one of the
+/// `allowed` patterns are committed. Patterns with a `/` match the path
+/// relative to the store, others the file name. Changes outside of the store
+/// are rejected, since the commit would include them unchecked.
+fn rejected_files(repo: &git2::Repository, prefix: &Path, allowed: &[String])
+    -> Result<Vec<String>>
+{
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(ref e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(VcsError::Git(e)),
+    };
+    let mut index = repo.index()?;
+    index.read(true)?;
+    let diff = repo.diff_tree_to_index(head.as_ref(), Some(&index), None)?;
+
+    let mut rejected = vec![];
+    for delta in diff.deltas() {
+        let file = delta.new_file();
+        let path = match file.path() {
+            Some(path) => path,
+            None => continue,
+        };
+        let path = match path.strip_prefix(prefix) {
+            Ok(path) if delta.status() != git2::Delta::Deleted => path,
+            Ok(_) => continue,
+            Err(_) => {
+                rejected.push(format!("{} (outside of the store)",
+                                      path.display()));
+                continue;
+            },
+        };
+        let content = || Ok(repo.find_blob(file.id())?.content().to_vec());
+        rejected.extend(rejection(path, content, allowed)?);
+    }
+    Ok(rejected)
+}
+
+/// Returns why the file at `path`, relative to the store, must not be
+/// committed, see `rejected_files`. Its `content` is only read for gpg-files.
+fn rejection<F>(path: &Path, content: F, allowed: &[String])
+    -> Result<Option<String>>
+    where F: FnOnce() -> Result<Vec<u8>>
+{
+    if path.extension().is_some_and(|e| e == "gpg") {
+        if !is_openpgp(&content()?) {
+            return Ok(Some(format!("{} (not an OpenPGP message)",
+                                   path.display())));
+        }
+    } else if !is_allowed(path, allowed) {
+        return Ok(Some(path.display().to_string()));
+    }
+    Ok(None)
+}
+
+/// Returns whether `path`, relative to the store, may be committed besides
+/// the gpg-files, see `rejected_files`.
+fn is_allowed(path: &Path, allowed: &[String]) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let path_str = path.to_str().unwrap_or("");
+    ALLOWED_FILES.contains(&name)
+        || path.iter().any(|c| c == ::template::TEMPLATES_DIR)
+        || allowed.iter().any(|p| {
+            ::filter::glob_match(p, if p.contains('/') { path_str } else { name })
+        })
+}
+
+/// Returns whether `content` is an OpenPGP message, either ASCII armored or
+/// binary, i.e. it starts with one of the `MESSAGE_PACKETS`. An empty file
+/// reveals nothing and is accepted.
+fn is_openpgp(content: &[u8]) -> bool {
+    let first = match content.first() {
+        None => return true,
+        Some(&b) if b & 0x80 == 0 =>
+            return content.starts_with(ARMOR_HEADER.as_bytes()),
+        Some(&b) => b,
+    };
+
+    // new format headers have the tag in the lower six bits and the length
+    // in the following octets, old format ones have the tag in bits 2 to 5
+    // and the size of the length in the lower two bits
+    let (tag, header_len) = if first & 0x40 != 0 {
+        let len = match content.get(1) {
+            Some(&l) if (192..224).contains(&l) => 2,
+            Some(&255) => 5,
+            _ => 1,
+        };
+        (first & 0x3f, 1 + len)
+    } else {
+        let len = match first & 0x03 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => 0,
+        };
+        ((first >> 2) & 0x0f, 1 + len)
+    };
+
+    let body = content.get(header_len);
+    MESSAGE_PACKETS.iter().any(|&(t, values)| {
+        t == tag && (values.is_empty() || body.is_some_and(|b| values.contains(b)))
+    })
+}
+
+impl HgWrapper {
+    pub fn new(repo_path: &str) -> HgWrapper {
+        HgWrapper {
+            repo: String::from(repo_path),
+            allowed: vec![],
+            ops: Operations::new(),
+        }
+    }
+
+    /// Returns the `hg` command, run in the store with a stable output.
+    fn hg(&self) -> Command {
+        let mut cmd = Command::new("hg");
+        cmd.current_dir(&self.repo)
+           .env("HGPLAIN", "1");
+        cmd
+    }
+
+    /// Runs the modifying `cmd` through the operations, like `GitWrapper`.
+    fn run(&self, cmd: &mut Command) -> Result<()> {
+        cmd.stdout(Stdio::inherit())
+           .stderr(Stdio::piped());
+        let output = self.ops.output(cmd)?;
+        check(cmd, &output)
+    }
+
+    /// Runs the reading `cmd` and returns its stdout.
+    fn read(&self, cmd: &mut Command) -> Result<Vec<u8>> {
+        let output = cmd.output()?;
+        check(cmd, &output)?;
+        Ok(output.stdout)
+    }
+
+    /// Returns the added and modified files of the store, which must not be
+    /// committed, see `rejected_files`. Files outside of the store are not
+    /// committed by `commit`, hence skipped.
+    fn rejected_files(&self) -> Result<Vec<String>> {
+        let root = self.read(self.hg().arg("root"))?;
+        let root = PathBuf::from(String::from_utf8_lossy(&root).trim());
+        let store = Path::new(&self.repo).canonicalize()?;
+        let prefix = store.strip_prefix(root.canonicalize()?)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        // paths are relative to the root without patterns
+        let output = self.read(self.hg().args(["status", "--added", "--modified",
+                                               "--no-status", "--print0"]))?;
+        let mut rejected = vec![];
+        for file in output.split(|&b| b == 0).filter(|f| !f.is_empty()) {
+            let file = PathBuf::from(String::from_utf8_lossy(file).as_ref());
+            let path = match file.strip_prefix(&prefix) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let content = || Ok(fs::read(root.join(&file))?);
+            rejected.extend(rejection(path, content, &self.allowed)?);
+        }
+        Ok(rejected)
+    }
+}
+
+impl VersionControl for HgWrapper {
+    fn add(&self, file: &str) -> Result<()> {
+        self.run(self.hg().arg("add").arg("--quiet").arg(file))
+    }
+
+    fn remove(&self, file: &str) -> Result<()> {
+        // the store removes the file itself
+        self.run(self.hg().arg("remove").arg("--after").arg("--quiet")
+                 .arg(file))
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        // unlike git, hg commits all changes of tracked files, hence the
+        // status and the commit are limited to the store by the pattern `.`
+        //
+        // changes are unknown in dry-run mode, since `add` is recorded
+        if !self.ops.is_dry_run() {
+            if self.read(self.hg().args(["status", "--added", "--modified",
+                                            "--removed", "."]))?.is_empty() {
+                return Err(VcsError::NothingToCommit);
+            }
+            let rejected = self.rejected_files()?;
+            if !rejected.is_empty() {
+                return Err(VcsError::Rejected(rejected));
+            }
+        }
+
+        let trailer = format!("{}: {}", RASS_TRAILER, env!("CARGO_PKG_VERSION"));
+        self.run(self.hg().arg("commit")
+                 .arg("--message")
+                 .arg(format!("{}\n\n{}", message, trailer))
+                 .arg("."))
+    }
+
+    fn cmd_dispatch(&self, args: Vec<&str>) -> Result<()> {
+        let mut cmd = Command::new("hg");
         cmd.args(args.as_slice())
            .current_dir(&self.repo);
-        cmd.status()
+        let status = self.ops.status(&mut cmd)?;
+        if !status.success() {
+            return Err(VcsError::Command(command_line(&cmd), status,
+                                         String::new()));
+        }
+        Ok(())
+    }
+
+    fn program(&self) -> Option<&'static str> {
+        Some("hg")
+    }
+
+    fn set_operations(&mut self, ops: Operations) {
+        self.ops = ops;
+    }
+}
+
+/// Returns the program and the subcommand of `cmd`, e.g. `git commit`.
+fn command_line(cmd: &Command) -> String {
+    let mut line = cmd.get_program().to_string_lossy().into_owned();
+    if let Some(arg) = cmd.get_args().next() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    line
+}
+
+/// Turns a non-zero exit of `cmd` into `VcsError::Command` with its stderr,
+/// the stderr of a successful command is dropped.
+fn check(cmd: &Command, output: &Output) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(VcsError::Command(command_line(cmd), output.status,
+                          stderr.trim().to_string()))
+}
+
+impl Git2Wrapper {
+    /// Opens the git repository which contains the store at `store`.
+    pub fn open(store: &Path) -> result::Result<Git2Wrapper, git2::Error> {
+        let repo = git2::Repository::discover(store)?;
+        let prefix = store_prefix(&repo, store)?;
+        let sign = repo.config()
+            .and_then(|c| c.get_bool("pass.signcommits"))
+            .unwrap_or(false);
+        let author = repo.config()
+            .and_then(|c| c.get_string("pass.author"))
+            .ok();
+
+        Ok(Git2Wrapper {
+            repo: repo,
+            store: store.to_path_buf(),
+            prefix: prefix,
+            sign: sign,
+            author: author,
+            signers: vec![],
+            allowed: vec![],
+            push: false,
+            ops: Operations::new(),
+        })
+    }
+
+    /// Returns the `git` command backend for the store, used for operations
+    /// which need the network or are not supported by libgit2 (rebase).
+    fn command(&self) -> GitWrapper {
+        GitWrapper {
+            repo: self.store.to_string_lossy().into_owned(),
+            sign: self.sign,
+            author: self.author.clone(),
+            signers: self.signers.clone(),
+            allowed: self.allowed.clone(),
+            push: false,
+            ops: self.ops.clone(),
+        }
+    }
+
+    /// Records the equivalent `git` command in dry-run mode, returns `false`
+    /// if the operation has to be executed.
+    fn record(&self, args: &[&str]) -> bool {
+        let mut cmd = vec![String::from("git")];
+        cmd.extend(args.iter().map(|a| a.to_string()));
+        self.ops.record(Operation::Command(cmd, Some(self.store.clone())))
+    }
+
+    fn workdir(&self) -> &Path {
+        // bare repositories are refused by `open`
+        self.repo.workdir().unwrap()
+    }
+
+    /// Returns the path of `file` relative to the working directory, `file`
+    /// is either absolute or relative to the store.
+    fn repo_path(&self, file: &str) -> PathBuf {
+        let file = Path::new(file);
+        if file.is_absolute() {
+            if let Ok(rel) = file.strip_prefix(&self.store) {
+                return self.prefix.join(rel);
+            }
+            if let Ok(rel) = file.strip_prefix(self.workdir()) {
+                return rel.to_path_buf();
+            }
+        }
+        self.prefix.join(file)
+    }
+
+    /// Returns the index, re-read from disk since the `git` command (e.g.
+    /// through `cmd_dispatch`) may have changed it in the meantime.
+    fn index(&self) -> Result<git2::Index> {
+        let mut index = self.repo.index()?;
+        index.read(true)?;
+        Ok(index)
+    }
+
+    fn head(&self) -> Result<Option<git2::Commit<'_>>> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?)),
+            Err(ref e) if e.code() == git2::ErrorCode::UnbornBranch ||
+                          e.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(VcsError::Git(e)),
+        }
+    }
+
+    fn add_path(&self, path: &Path) -> Result<()> {
+        let mut index = self.index()?;
+        if self.workdir().join(path).is_dir() {
+            index.add_all([path], git2::IndexAddOption::DEFAULT, None)?;
+        } else {
+            index.add_path(path)?;
+        }
+        Ok(index.write()?)
+    }
+
+    fn remove_path(&self, path: &Path) -> Result<()> {
+        let mut index = self.index()?;
+        index.remove_path(path)?;
+        index.remove_dir(path, 0)?;
+        Ok(index.write()?)
+    }
+
+    /// Commits the index and returns the id of the new commit.
+    fn commit_index(&self, message: &str) -> Result<git2::Oid> {
+        let mut index = self.index()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let parent = self.head()?;
+        if parent.as_ref().map(|p| p.tree_id()) == Some(tree.id()) {
+            return Err(VcsError::NothingToCommit);
+        }
+        let rejected = rejected_files(&self.repo, &self.prefix, &self.allowed)?;
+        if !rejected.is_empty() {
+            return Err(VcsError::Rejected(rejected));
+        }
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let author = match self.author {
+            Some(ref author) => parse_author(author)?,
+            None => self.repo.signature()?,
+        };
+        // without an own identity the author also commits
+        let committer = self.repo.signature()
+            .unwrap_or_else(|_| author.clone());
+
+        let id = if self.sign {
+            let buffer = self.repo.commit_create_buffer(
+                &author, &committer, message, &tree, &parents)?;
+            let buffer = buffer.as_str()
+                .ok_or_else(|| git2::Error::from_str("invalid commit"))?;
+            let signature = self.sign_buffer(buffer)?;
+            let id = self.repo.commit_signed(buffer, &signature, None)?;
+            self.update_head(id, message)?;
+            id
+        } else {
+            self.repo.commit(Some("HEAD"), &author, &committer, message, &tree,
+                             &parents)?
+        };
+        // e.g. finishes a revert
+        self.repo.cleanup_state()?;
+        Ok(id)
+    }
+
+    /// Creates an armored signature of the commit `buffer`, with the key of
+    /// `user.signingkey` or the default key of gpg.
+    fn sign_buffer(&self, buffer: &str) -> Result<String> {
+        let gpg_error = |e: gpgme::Error| git2::Error::from_str(&e.to_string());
+
+        let mut ctx = gpgme::Context::from_protocol(gpgme::Protocol::OpenPgp)
+            .map_err(&gpg_error)?;
+        ctx.set_armor(true);
+        if let Ok(key) = self.repo.config()?.get_string("user.signingkey") {
+            let key = ctx.find_secret_key(key).map_err(&gpg_error)?;
+            ctx.add_signer(&key).map_err(&gpg_error)?;
+        }
+        let mut signature = Vec::new();
+        ctx.sign_detached(buffer, &mut signature).map_err(&gpg_error)?;
+        Ok(String::from_utf8(signature)
+            .map_err(|e| git2::Error::from_str(&e.to_string()))?)
+    }
+
+    /// Points the current branch (or a detached `HEAD`) to commit `id`.
+    fn update_head(&self, id: git2::Oid, message: &str) -> Result<()> {
+        let head = self.repo.find_reference("HEAD")?;
+        let log = format!("commit: {}", message.lines().next().unwrap_or(""));
+        match head.symbolic_target() {
+            Some(branch) => { self.repo.reference(branch, id, true, &log)?; },
+            None => self.repo.set_head_detached(id)?,
+        }
+        Ok(())
+    }
+
+    fn log_commits(&self, path: Option<&Path>, max: usize)
+        -> Result<Vec<Commit>>
+    {
+        if self.head()?.is_none() {
+            return Ok(vec![]);
+        }
+        let mut walk = self.repo.revwalk()?;
+        walk.push_head()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        let mut commits = vec![];
+        for id in walk {
+            if commits.len() >= max {
+                break;
+            }
+            let commit = self.repo.find_commit(id?)?;
+            if let Some(path) = path {
+                if !self.changes_path(&commit, path)? {
+                    continue;
+                }
+            }
+            let author = commit.author();
+            commits.push(Commit {
+                id: commit.id().to_string(),
+                author: author.name().unwrap_or("").to_string(),
+                date: format_time(&author.when()),
+                message: commit.message().unwrap_or("").trim_end().to_string(),
+            });
+        }
+        Ok(commits)
+    }
+
+    /// Returns whether `commit` changed `path` compared to its first parent.
+    fn changes_path(&self, commit: &git2::Commit, path: &Path)
+        -> Result<bool>
+    {
+        let id = |tree: &git2::Tree| tree.get_path(path).ok().map(|e| e.id());
+        let current = id(&commit.tree()?);
+        let previous = match commit.parents().next() {
+            Some(parent) => id(&parent.tree()?),
+            None => None,
+        };
+        Ok(current != previous)
+    }
+
+    fn blob_at(&self, rev: &str, path: &Path) -> Result<Vec<u8>> {
+        let tree = self.repo.revparse_single(rev)?.peel_to_tree()?;
+        let blob = self.repo.find_blob(tree.get_path(path)?.id())?;
+        Ok(blob.content().to_vec())
+    }
+
+    fn revert_commit(&self, id: &str) -> Result<()> {
+        let commit = self.repo.revparse_single(id)?.peel_to_commit()?;
+        self.index()?;
+        self.repo.revert(&commit, None)?;
+
+        let index = self.index()?;
+        if index.has_conflicts() {
+            let paths = index.conflicts()?
+                .filter_map(|c| c.ok())
+                .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+                .map(|e| String::from_utf8_lossy(&e.path).into_owned())
+                .collect();
+            return Err(VcsError::Conflict(paths));
+        }
+        Ok(())
+    }
+
+    fn changed_paths(&self) -> Result<Vec<String>> {
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(true);
+        let statuses = self.repo.statuses(Some(&mut opts))?;
+        Ok(statuses.iter()
+            .filter_map(|s| s.path().map(PathBuf::from))
+            .filter_map(|p| {
+                p.strip_prefix(&self.prefix).ok()
+                    .map(|p| p.to_string_lossy().into_owned())
+            })
+            .collect())
     }
 }
 
+impl VersionControl for Git2Wrapper {
+    fn add(&self, file: &str) -> Result<()> {
+        if !self.record(&["add", file]) {
+            self.add_path(&self.repo_path(file))?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, file: &str) -> Result<()> {
+        if !self.record(&["rm", "-qr", file]) {
+            let path = self.repo_path(file);
+            self.remove_path(&path)?;
+            // like `git rm`, which also removes the file
+            let path = self.workdir().join(path);
+            if path.is_file() {
+                self.ops.remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&self, message: &str) -> Result<()> {
+        let trailer = format!("{}: {}", RASS_TRAILER, env!("CARGO_PKG_VERSION"));
+        let mut args = vec!["commit", "-m", message, "-m", &trailer];
+        if self.sign {
+            args.push("-S");
+        }
+        if !self.record(&args) {
+            let message = format!("{}\n\n{}\n", message, trailer);
+            self.commit_index(&message)?;
+        }
+
+        if self.push {
+            // libgit2 is built without network transports
+            self.command().push()?;
+        }
+        Ok(())
+    }
+
+    fn cmd_dispatch(&self, args: Vec<&str>) -> Result<()> {
+        self.command().cmd_dispatch(args)
+    }
+
+    fn program(&self) -> Option<&'static str> {
+        Some("git")
+    }
+
+    fn log(&self, path: Option<&str>, max: usize) -> Result<Vec<Commit>> {
+        let path = path.map(|p| self.prefix.join(p));
+        self.log_commits(path.as_deref(), max)
+    }
+
+    fn file_at(&self, rev: &str, path: &str) -> Result<Vec<u8>> {
+        self.blob_at(rev, &self.prefix.join(path))
+    }
+
+    fn revert(&self, id: &str) -> Result<()> {
+        if self.record(&["revert", "--no-commit", id]) {
+            return Ok(());
+        }
+        self.revert_commit(id)
+    }
+
+    fn status(&self) -> Result<Vec<String>> {
+        self.changed_paths()
+    }
+
+    fn pull(&self) -> Result<Vec<MergeConflict>> {
+        self.command().pull()
+    }
+
+    fn continue_pull(&self) -> Result<Vec<MergeConflict>> {
+        self.command().continue_pull()
+    }
+
+    fn abort_pull(&self) -> Result<()> {
+        self.command().abort_pull()
+    }
+
+    fn push(&self) -> Result<()> {
+        self.command().push()
+    }
+
+    fn set_operations(&mut self, ops: Operations) {
+        self.ops = ops;
+    }
+}
+
+/// Formats `time` in ISO 8601 format, like `%aI` of `git log`.
+fn format_time(time: &git2::Time) -> String {
+    let offset = time.offset_minutes() as i64;
+    let local = time.seconds() + offset * 60;
+    let (y, m, d) = ::util::civil_from_days(local.div_euclid(86400));
+    let secs = local.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+            y, m, d, secs / 3600, secs / 60 % 60, secs % 60,
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 60, offset.abs() % 60)
+}
+
+
+/// Parses an author of the form `Name <email>`, e.g. the value of
+/// `pass.author`.
+fn parse_author(author: &str) -> Result<git2::Signature<'static>> {
+    let invalid = || VcsError::Git(git2::Error::from_str(
+        &format!("invalid author '{}', expected 'Name <email>'", author)));
+    let start = author.find('<').ok_or_else(&invalid)?;
+    let end = author.rfind('>').filter(|&end| end > start)
+        .ok_or_else(&invalid)?;
+    let name = author[..start].trim();
+    let email = author[start + 1..end].trim();
+    if name.is_empty() || email.is_empty() {
+        return Err(invalid());
+    }
+    Ok(git2::Signature::now(name, email)?)
+}
+
+/// Parses the output of `git log` in the format used by `GitWrapper::log`.
+fn parse_log(output: &str) -> Vec<Commit> {
+    output.split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(4, '\x1f');
+            Some(Commit {
+                id: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                message: fields.next()?.trim_end().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::process::{self, Command};
+    use git2;
+    use ::testutil::{git, git_clone, git_init, gpg_key, temp_dir};
+    use super::{format_time, incoming_revisions, is_openpgp, is_rass_commit,
+                parse_log, Git2Wrapper, HgWrapper, NoVcs, VcsError,
+                VersionControl};
+
+    #[test]
+    fn test_parse_log() {
+        let output = "a1\x1fAlice\x1f2020-01-01T10:00:00+01:00\x1fEdit password foo in store.\n\n\
+                      Rass-Commit: 0.4.0\n\x1e\n\
+                      b2\x1fBob\x1f2019-12-31T10:00:00+01:00\x1fFix typo\n\x1e\n";
+        let commits = parse_log(output);
+
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].id, "a1");
+        assert_eq!(commits[0].subject(), "Edit password foo in store.");
+        assert_eq!(commits[1].author, "Bob");
+        assert_eq!(commits[1].message, "Fix typo");
+    }
+
+    #[test]
+    fn test_is_rass_commit() {
+        assert!(is_rass_commit("Undo foo\n\nRass-Commit: 0.4.0"));
+        assert!(is_rass_commit("Add given password web/mail to store."));
+        assert!(is_rass_commit("Remove web/mail from store."));
+        assert!(!is_rass_commit("Add given password to the README"));
+        assert!(!is_rass_commit("Fix typo"));
+    }
+
+    #[test]
+    fn test_is_openpgp() {
+        // old and new format headers of the packets which start a message
+        for content in &[&b""[..], b"\x84\x0c\x03", b"\x85\x01\x0c\x03",
+                         b"\x8c\x0d\x04", b"\xa3\x01", b"\xc1\x0c\x03",
+                         b"\xc1\xc0\x0c\x03", b"\xd2\x40\x01",
+                         b"-----BEGIN PGP MESSAGE-----\n"] {
+            assert!(is_openpgp(content), "{:?}", content);
+        }
+        // plain text (also UTF-8), a BOM, gzip, a signature packet and
+        // packets with an invalid version
+        for content in &[&b"secret"[..], "\u{fc}ber".as_bytes(),
+                         "\u{c4}".as_bytes(), b"\xef\xbb\xbfsecret",
+                         b"\x1f\x8b\x08", b"\x89\x01", b"\x84\x0c\x04",
+                         b"\xd2\x40"] {
+            assert!(!is_openpgp(content), "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(&git2::Time::new(946_684_800, 60)),
+                   "2000-01-01T01:00:00+01:00");
+        assert_eq!(format_time(&git2::Time::new(0, -90)),
+                   "1969-12-31T22:30:00-01:30");
+    }
+
+    #[test]
+    fn test_git2_wrapper() {
+        let dir = temp_dir("git2");
+        let home = dir.join("store");
+        fs::create_dir_all(home.join("web")).unwrap();
+        git_init(&dir);
+
+        let git = Git2Wrapper::open(&home).unwrap();
+        fs::write(home.join("web/mail.gpg"), b"\x84\x0c\x03v1").unwrap();
+        assert_eq!(git.status().unwrap(), vec!["web/mail.gpg"]);
+        git.add(home.join("web/mail.gpg").to_str().unwrap()).unwrap();
+        git.commit("Add given password web/mail to store.").unwrap();
+        assert!(git.status().unwrap().is_empty());
+        match git.commit("Nothing") {
+            Err(VcsError::NothingToCommit) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        fs::write(home.join("web/mail.gpg"), b"\x84\x0c\x03v2").unwrap();
+        fs::write(home.join(".gitignore"), "").unwrap();
+        git.add("web/mail.gpg").unwrap();
+        git.add(".gitignore").unwrap();
+        git.commit("Edit password web/mail in store.").unwrap();
+
+        let log = git.log(Some("web/mail.gpg"), 10).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].author, "rass");
+        assert!(is_rass_commit(&log[0].message));
+        assert_eq!(git.file_at(&log[1].id, "web/mail.gpg").unwrap(),
+                   b"\x84\x0c\x03v1");
+        assert_eq!(git.file_at("HEAD", "web/mail.gpg").unwrap(), b"\x84\x0c\x03v2");
+        assert!(git.file_at("HEAD", "web/none.gpg").is_err());
+
+        git.remove("web/mail.gpg").unwrap();
+        git.commit("Remove web/mail from store.").unwrap();
+        assert!(!home.join("web/mail.gpg").exists());
+        assert_eq!(git.log(None, 10).unwrap().len(), 3);
+        assert_eq!(git.log(Some(".gitignore"), 10).unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_init() {
+        use ::config::Config;
+        use ::ops::Operations;
+
+        let dir = temp_dir("vcs-init");
+        fs::write(dir.join(".gitattributes"), "*.txt text").unwrap();
+        fs::write(dir.join(".gpg-id"), "ABCDEF").unwrap();
+        let config = Config {
+            store: dir.clone(),
+            ..Config::default()
+        };
+
+        let git = super::init(&config, Operations::new()).unwrap();
+        git_init(&dir);
+        let repo = git2::Repository::open(&dir).unwrap();
+        git.add(".gpg-id").unwrap();
+        git.commit("Initialize password store.").unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join(".gitattributes")).unwrap(),
+                   "*.txt text\n*.gpg diff=gpg\n");
+        assert_eq!(fs::read_to_string(dir.join(".gitignore")).unwrap(),
+                   "/.rass.lock\n");
+        let git_config = repo.config().unwrap().snapshot().unwrap();
+        assert_eq!(git_config.get_str("diff.gpg.textconv").unwrap(),
+                   super::GPG_TEXTCONV);
+        assert!(git.status().unwrap().is_empty());
+
+        // a second init changes nothing
+        let git = super::init(&config, Operations::new()).unwrap();
+        match git.commit("Initialize password store.") {
+            Err(VcsError::NothingToCommit) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(git.log(None, 10).unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_author() {
+        use ::config::{self, Config};
+
+        let dir = temp_dir("vcs-author");
+        git_init(&dir);
+        git(&dir, &["config", "pass.author", "Audit <audit@example.com>"]);
+        let repo = git2::Repository::open(&dir).unwrap();
+
+        let mut config = Config {
+            store: dir.clone(),
+            ..Config::default()
+        };
+        for (i, backend) in [config::GIT_BACKEND_LIBGIT2,
+                             config::GIT_BACKEND_COMMAND].iter().enumerate() {
+            config.git_backend = backend.to_string();
+            let git = super::from_config(&config).unwrap();
+            let file = format!("{}.gpg", i);
+            fs::write(dir.join(&file), "").unwrap();
+            git.add(&file).unwrap();
+            git.commit("Add given password to store.").unwrap();
+
+            let head = repo.head().unwrap().peel_to_commit().unwrap();
+            assert_eq!(head.author().email(), Some("audit@example.com"));
+            assert_eq!(head.committer().name(), Some("rass"));
+        }
+
+        git(&dir, &["config", "pass.author", "Audit"]);
+        fs::write(dir.join("2.gpg"), "").unwrap();
+        for backend in &[config::GIT_BACKEND_LIBGIT2,
+                         config::GIT_BACKEND_COMMAND] {
+            config.git_backend = backend.to_string();
+            let git = super::from_config(&config).unwrap();
+            git.add("2.gpg").unwrap();
+            match git.commit("Add given password to store.") {
+                Err(VcsError::Git(ref err)) =>
+                    assert!(err.message().starts_with("invalid author")),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejected_files() {
+        use ::config::{self, Config};
+        use ::ops::Operations;
+
+        let dir = temp_dir("vcs-rejected");
+        let store = dir.join("store");
+        fs::create_dir_all(store.join(".templates")).unwrap();
+        git_init(&dir);
+        fs::write(dir.join("README"), "").unwrap();
+        git(&dir, &["add", "README"]);
+
+        // changes outside of the store are not committed by rass
+        let mut config = Config {
+            store: store.clone(),
+            allowed_files: vec![String::from("*.md")],
+            ..Config::default()
+        };
+        let files = [("foo.gpg", &b"\x84\x0c\x03"[..]), ("new.gpg", b"\xd2\x40\x01"),
+                     ("bar.gpg", b"-----BEGIN PGP MESSAGE-----"),
+                     ("foo.gpg~", b"secret"), ("baz.gpg", b"secret"),
+                     ("umlaut.gpg", "\u{fc}ber".as_bytes()),
+                     ("README.md", b""), (".templates/default", b"")];
+        for &(name, content) in &files {
+            fs::write(store.join(name), content).unwrap();
+        }
+        for backend in &[config::GIT_BACKEND_LIBGIT2,
+                         config::GIT_BACKEND_COMMAND] {
+            config.git_backend = backend.to_string();
+            let git = super::from_config(&config).unwrap();
+            for &(name, _) in &files {
+                git.add(name).unwrap();
+            }
+            match git.commit("Add files") {
+                Err(VcsError::Rejected(ref files)) =>
+                    assert_eq!(files, &["README (outside of the store)",
+                                        "baz.gpg (not an OpenPGP message)",
+                                        "foo.gpg~",
+                                        "umlaut.gpg (not an OpenPGP message)"]),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+
+        // the hook refuses the same files for `git commit`
+        let hook = super::install_hook(&config, &Operations::new()).unwrap();
+        assert!(hook.is_file());
+        let commit = |dir: &Path| Command::new("git")
+            .args(["commit", "-q", "-m", "Add files"]).current_dir(dir)
+            .stderr(process::Stdio::null()).status().unwrap().success();
+        assert!(!commit(&store));
+        git(&store, &["rm", "-q", "--cached", "foo.gpg~"]);
+        assert!(!commit(&store));
+        git(&store, &["rm", "-q", "--cached", "baz.gpg"]);
+        assert!(!commit(&store));
+        git(&store, &["rm", "-q", "--cached", "umlaut.gpg"]);
+        assert!(commit(&store));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::PathBuf;
+        use ::config::{self, Config};
+
+        let dir = temp_dir("vcs-errors");
+        let mut config = Config {
+            store: dir.clone(),
+            ..Config::default()
+        };
+        match super::from_config(&config) {
+            Err(VcsError::NoRepository(ref p)) if *p == dir => (),
+            _ => panic!("{:?} is not a repository", dir),
+        }
+        config.vcs = config::VCS_NONE.to_string();
+        assert!(super::from_config(&config).is_ok());
+        let err = NoVcs.log(None, 1).unwrap_err();
+        assert_eq!(err.to_string(),
+                   "history is not supported without version control");
+        let err = HgWrapper::new(dir.to_str().unwrap()).push().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "push is not supported by the hg backend");
+
+        git_init(&dir);
+        let repo = git2::Repository::open(&dir).unwrap();
+        let hook = PathBuf::from(repo.path()).join("hooks/pre-commit");
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "#!/bin/sh\necho rejected by hook >&2\nexit 1\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        config.vcs = config::VCS_AUTO.to_string();
+        config.git_backend = config::GIT_BACKEND_COMMAND.to_string();
+        let git = super::from_config(&config).unwrap();
+        match git.commit("Nothing") {
+            Err(VcsError::NothingToCommit) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        fs::write(dir.join("foo.gpg"), "").unwrap();
+        git.add("foo.gpg").unwrap();
+        match git.commit("Add given password foo to store.") {
+            Err(VcsError::Command(ref cmd, status, ref stderr)) => {
+                assert_eq!(cmd, "git commit");
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "rejected by hook");
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pull_conflict() {
+        let root = temp_dir("pull");
+        git(&root, &["init", "-q", "--bare", "remote.git"]);
+        let a = git_clone(&root, "a");
+        fs::write(a.join("foo.gpg"), "base").unwrap();
+        git(&a, &["add", "foo.gpg"]);
+        git(&a, &["commit", "-q", "-m", "Add foo"]);
+        git(&a, &["push", "-q", "-u", "origin", "HEAD"]);
+        let b = git_clone(&root, "b");
+
+        fs::write(a.join("foo.gpg"), "remote").unwrap();
+        git(&a, &["commit", "-q", "-am", "Edit foo on a"]);
+        git(&a, &["push", "-q"]);
+
+        let git_b = Git2Wrapper::open(&b).unwrap();
+        fs::write(b.join("foo.gpg"), b"\x84\x0c\x03local").unwrap();
+        git_b.add("foo.gpg").unwrap();
+        git_b.commit("Edit foo on b").unwrap();
+
+        let conflicts = git_b.pull().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "foo.gpg");
+        assert_eq!(conflicts[0].base, Some(b"base".to_vec()));
+        assert_eq!(conflicts[0].local, Some(b"\x84\x0c\x03local".to_vec()));
+        assert_eq!(conflicts[0].remote, Some(b"remote".to_vec()));
+
+        fs::write(b.join("foo.gpg"), "merged").unwrap();
+        git_b.add("foo.gpg").unwrap();
+        assert!(git_b.continue_pull().unwrap().is_empty());
+        git_b.push().unwrap();
+
+        let log = git_b.log(None, 10).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[0].subject(), "Edit foo on b");
+        assert_eq!(log[1].subject(), "Edit foo on a");
+        assert_eq!(git_b.file_at("origin/HEAD", "foo.gpg").unwrap(), b"merged");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_commits() {
+        use ::config::Config;
+
+        let root = temp_dir("untrusted");
+        git(&root, &["init", "-q", "--bare", "remote.git"]);
+        let a = git_clone(&root, "a");
+        fs::write(a.join("foo.gpg"), "base").unwrap();
+        git(&a, &["add", "foo.gpg"]);
+        git(&a, &["commit", "-q", "-m", "Add foo"]);
+        git(&a, &["push", "-q", "-u", "origin", "HEAD"]);
+        let b = git_clone(&root, "b");
+
+        let config = Config {
+            store: b.clone(),
+            commit_signers: vec![String::from("0123 4567 89AB CDEF")],
+            ..Config::default()
+        };
+        let git_b = super::from_config(&config).unwrap();
+
+        // changes of other files are not verified
+        fs::write(a.join("README"), "").unwrap();
+        git(&a, &["add", "README"]);
+        git(&a, &["commit", "-q", "-m", "Add README"]);
+        git(&a, &["push", "-q"]);
+        assert!(git_b.pull().unwrap().is_empty());
+
+        // a conflicting `git pull` of trusted commits stops in the merge
+        fs::write(a.join("README"), "a").unwrap();
+        git(&a, &["commit", "-q", "-am", "Edit README"]);
+        git(&a, &["push", "-q"]);
+        fs::write(b.join("README"), "b").unwrap();
+        git(&b, &["commit", "-q", "-am", "Edit README in b"]);
+        match git_b.cmd_dispatch(vec!["pull", "-q", "--no-rebase"]) {
+            Err(VcsError::Command(..)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(b.join(".git/MERGE_HEAD").is_file());
+        git(&b, &["merge", "--abort"]);
+        git(&b, &["reset", "-q", "--hard", "@{upstream}"]);
+
+        fs::write(a.join("foo.gpg"), "evil").unwrap();
+        git(&a, &["commit", "-q", "-am", "Edit foo"]);
+        git(&a, &["push", "-q"]);
+        match git_b.pull() {
+            Err(VcsError::Untrusted(ref commits)) => {
+                assert_eq!(commits.len(), 1);
+                assert!(commits[0].ends_with(" Edit foo"));
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(fs::read(b.join("foo.gpg")).unwrap(), b"base");
+
+        // `git pull` does not merge untrusted commits, also if they conflict
+        fs::write(b.join("foo.gpg"), "local").unwrap();
+        git(&b, &["commit", "-q", "-am", "Edit foo in b"]);
+        match git_b.cmd_dispatch(vec!["pull", "-q"]) {
+            Err(VcsError::Untrusted(ref commits)) => assert_eq!(commits.len(), 1),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(!b.join(".git/MERGE_HEAD").exists());
+        assert_eq!(fs::read(b.join("foo.gpg")).unwrap(), b"local");
+        assert_eq!(git_b.log(None, 10).unwrap()[0].subject(), "Edit foo in b");
+
+        // neither does a merge or rebase after `git fetch`
+        git_b.cmd_dispatch(vec!["fetch", "-q"]).unwrap();
+        for args in [vec!["merge", "@{upstream}"], vec!["rebase"]] {
+            match git_b.cmd_dispatch(args) {
+                Err(VcsError::Untrusted(..)) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert_eq!(fs::read(b.join("foo.gpg")).unwrap(), b"local");
+
+        // nothing is pulled into an empty repository
+        let c = root.join("c");
+        fs::create_dir_all(&c).unwrap();
+        git_init(&c);
+        let config = Config { store: c.clone(), ..config };
+        let git_c = super::from_config(&config).unwrap();
+        match git_c.cmd_dispatch(vec!["pull", "-q", "../remote.git"]) {
+            Err(VcsError::Untrusted(ref commits)) => assert_eq!(commits.len(), 2),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(!c.join("foo.gpg").exists());
+        assert!(!c.join("README").exists());
+        assert!(git2::Repository::open(&c).unwrap().head().is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_incoming_revisions() {
+        assert_eq!(incoming_revisions(&["merge"]), ["@{upstream}"]);
+        assert_eq!(incoming_revisions(&["merge", "-m", "msg", "a", "b"]),
+                   ["a", "b"]);
+        assert_eq!(incoming_revisions(&["rebase", "-q", "origin/main", "topic"]),
+                   ["origin/main"]);
+        assert_eq!(incoming_revisions(&["rebase", "--onto", "x", "y"]),
+                   ["x", "y"]);
+        assert!(incoming_revisions(&["rebase", "--continue"]).is_empty());
+        assert!(incoming_revisions(&["merge", "--abort"]).is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs gpg and gpgme, changes GNUPGHOME"]
+    fn test_trusted_commits() {
+        use ::config::Config;
+
+        let root = temp_dir("trusted");
+        let fpr = gpg_key();
+
+        git(&root, &["init", "-q", "--bare", "remote.git"]);
+        let a = git_clone(&root, "a");
+        fs::write(a.join("foo.gpg"), "base").unwrap();
+        git(&a, &["add", "foo.gpg"]);
+        git(&a, &["commit", "-q", "-m", "Add foo"]);
+        git(&a, &["push", "-q", "-u", "origin", "HEAD"]);
+        let b = git_clone(&root, "b");
+
+        let config = Config {
+            store: b.clone(),
+            commit_signers: vec![fpr.clone()],
+            ..Config::default()
+        };
+        let git_b = super::from_config(&config).unwrap();
+
+        fs::write(a.join("foo.gpg"), "signed").unwrap();
+        git(&a, &["-c", &format!("user.signingkey={}", fpr), "commit", "-q",
+                  "-S", "-am", "Edit foo"]);
+        git(&a, &["push", "-q"]);
+        assert!(git_b.pull().unwrap().is_empty());
+        assert_eq!(fs::read(b.join("foo.gpg")).unwrap(), b"signed");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs Mercurial"]
+    fn test_hg_wrapper() {
+        use ::config::Config;
+
+        let dir = temp_dir("hg");
+        let store = dir.join("store");
+        fs::create_dir_all(store.join("web")).unwrap();
+        assert!(Command::new("hg").args(["init", "--quiet"]).current_dir(&dir)
+                .status().unwrap().success());
+        fs::write(dir.join(".hg/hgrc"),
+                  "[ui]\nusername = rass <rass@example.com>\n").unwrap();
+
+        let config = Config {
+            store: store.clone(),
+            ..Config::default()
+        };
+        let hg = super::from_config(&config).unwrap();
+        assert_eq!(hg.program(), Some("hg"));
+
+        // changes outside of the store are neither checked nor committed
+        let hg_run = |args: &[&str]| {
+            let output = Command::new("hg").args(args).current_dir(&dir)
+                .env("HGPLAIN", "1").output().unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+        fs::write(dir.join("deploy.sh"), "v1").unwrap();
+        hg_run(&["add", "--quiet", "deploy.sh"]);
+        hg_run(&["commit", "--message", "Add deploy.sh"]);
+        fs::write(dir.join("deploy.sh"), "v2").unwrap();
+        match hg.commit("Nothing") {
+            Err(VcsError::NothingToCommit) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        fs::write(store.join("web/mail.gpg"), b"\x84\x0c\x03v1").unwrap();
+        hg.add(store.join("web/mail.gpg").to_str().unwrap()).unwrap();
+        hg.commit("Add given password web/mail to store.").unwrap();
+
+        fs::write(store.join("web/mail.gpg~"), "secret").unwrap();
+        hg.add("web/mail.gpg~").unwrap();
+        match hg.commit("Add backup") {
+            Err(VcsError::Rejected(ref files)) =>
+                assert_eq!(files, &["web/mail.gpg~"]),
+            r => panic!("unexpected result {:?}", r),
+        }
+        hg.cmd_dispatch(vec!["forget", "--quiet", "web/mail.gpg~"]).unwrap();
+
+        fs::remove_file(store.join("web/mail.gpg")).unwrap();
+        hg.remove(store.join("web/mail.gpg").to_str().unwrap()).unwrap();
+        hg.commit("Remove web/mail from store.").unwrap();
+
+        let log = hg_run(&["log", "--template", "{desc}\n"]);
+        assert_eq!(log.matches("Rass-Commit:").count(), 2);
+        assert!(log.starts_with("Remove web/mail from store.\n\nRass-Commit:"));
+        assert_eq!(hg_run(&["status", "--modified", "--no-status"]),
+                   "deploy.sh\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}